@@ -4,10 +4,13 @@
 // operations per second (each operation is one get() + put() pair)
 
 use sbitmap::Sbitmap;
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::env;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::thread;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant, SystemTime};
 
 #[cfg(target_os = "linux")]
@@ -22,7 +25,7 @@ struct SimpleBitmap {
 
 impl SimpleBitmap {
     fn new(depth: usize) -> Self {
-        let num_words = (depth + 63) / 64;
+        let num_words = depth.div_ceil(64);
         let words = (0..num_words).map(|_| AtomicUsize::new(0)).collect();
 
         Self { depth, words }
@@ -76,11 +79,141 @@ impl SimpleBitmap {
     }
 }
 
+/// Number of sub-buckets per power of two, as log2 (16 sub-buckets, ~6% resolution)
+const HIST_SUB_BITS: u32 = 4;
+const HIST_SUB_COUNT: usize = 1 << HIST_SUB_BITS;
+const HIST_BUCKETS: usize = (64 - HIST_SUB_BITS as usize + 1) * HIST_SUB_COUNT;
+
+/// Log-linear latency histogram in nanoseconds
+///
+/// Values below 16ns get exact buckets; above that each power of two is split
+/// into 16 linear sub-buckets, so any recorded value is reported within ~6%.
+/// Recording is a couple of shifts and an increment, cheap enough to call
+/// from the benchmark loop on sampled operations.
+#[derive(Clone)]
+struct LatencyHistogram {
+    buckets: Vec<u64>,
+    count: u64,
+    max: u64,
+}
+
+impl LatencyHistogram {
+    fn new() -> Self {
+        Self {
+            buckets: vec![0; HIST_BUCKETS],
+            count: 0,
+            max: 0,
+        }
+    }
+
+    fn bucket_index(value: u64) -> usize {
+        if value < HIST_SUB_COUNT as u64 {
+            return value as usize;
+        }
+        let exp = 63 - value.leading_zeros();
+        let sub = (value >> (exp - HIST_SUB_BITS)) as usize & (HIST_SUB_COUNT - 1);
+        ((exp - HIST_SUB_BITS + 1) as usize) * HIST_SUB_COUNT + sub
+    }
+
+    /// Highest value that maps to the given bucket
+    fn bucket_upper(index: usize) -> u64 {
+        if index < HIST_SUB_COUNT {
+            return index as u64;
+        }
+        let exp = (index / HIST_SUB_COUNT) as u32 + HIST_SUB_BITS - 1;
+        let sub = (index % HIST_SUB_COUNT) as u64;
+        let width = 1u64 << (exp - HIST_SUB_BITS);
+        (1u64 << exp) + sub * width + (width - 1)
+    }
+
+    fn record(&mut self, nanos: u64) {
+        self.buckets[Self::bucket_index(nanos)] += 1;
+        self.count += 1;
+        self.max = self.max.max(nanos);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        for (a, b) in self.buckets.iter_mut().zip(&other.buckets) {
+            *a += b;
+        }
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+    }
+
+    /// Value at the given percentile (0.0..=100.0), or 0 if nothing was recorded
+    fn percentile(&self, pct: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((pct / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Self::bucket_upper(i).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// Percentiles reported for get() latency
+const PERCENTILES: [(&str, f64); 5] = [
+    ("p50", 50.0),
+    ("p90", 90.0),
+    ("p99", 99.0),
+    ("p99.9", 99.9),
+    ("p99.99", 99.99),
+];
+
+/// Result of one benchmark task
+struct TaskResult {
+    /// Completed allocations, or frees for a consumer task
+    ops: u64,
+    /// Allocation attempts that found no free bits
+    failed: u64,
+    /// Whether the task only freed bits allocated by other tasks
+    consumer: bool,
+    latency: LatencyHistogram,
+}
+
+/// Result of one benchmark run across all tasks
+struct BenchResult {
+    name: String,
+    task_ops: Vec<u64>,
+    consumer_frees: Vec<u64>,
+    total_ops: u64,
+    failed_gets: u64,
+    ops_per_sec: f64,
+    latency: LatencyHistogram,
+}
+
+/// Time one allocation if this iteration is sampled
+///
+/// Sampling keeps the two clock reads off most iterations so the histogram
+/// does not distort the throughput being measured.
+#[inline]
+fn timed_get<T>(
+    iteration: u64,
+    sample_every: u64,
+    latency: &mut LatencyHistogram,
+    get: impl FnOnce() -> T,
+) -> T {
+    if sample_every == 0 || iteration % sample_every != 0 {
+        return get();
+    }
+    let start = Instant::now();
+    let result = get();
+    latency.record(start.elapsed().as_nanos() as u64);
+    result
+}
+
 /// Initialize allocation hint combining stack address + system time for better randomization
 ///
 /// This creates a pseudo-random starting point for each task by combining:
 /// - Stack address (different for each thread)
 /// - Current time in nanoseconds
+///
 /// This helps spread allocations across the bitmap and reduce contention.
 fn init_hint(depth: usize) -> usize {
     let stack_var = 0u8;
@@ -93,60 +226,455 @@ fn init_hint(depth: usize) -> usize {
 }
 
 /// Run benchmark workload: continuous get() and put() operations
-fn run_workload<B>(bitmap: Arc<B>, duration: Duration, ops_counter: Arc<AtomicU64>, depth: usize)
+fn run_workload<B>(
+    bitmap: Arc<B>,
+    duration: Duration,
+    sample_every: u64,
+    cpus: Option<Vec<usize>>,
+) -> JoinHandle<TaskResult>
 where
     B: Send + Sync + 'static,
     B: BitmapOps,
 {
     thread::spawn(move || {
+        bind_current_thread(cpus.as_deref());
         let start = Instant::now();
         let mut local_ops = 0u64;
-        let mut hint = init_hint(depth);
+        let mut failed = 0u64;
+        let mut iteration = 0u64;
+        let mut latency = LatencyHistogram::new();
+        let mut ctx = bitmap.new_ctx();
 
         while start.elapsed() < duration {
             // One operation = get() + put()
-            if let Some(bit) = bitmap.get(&mut hint) {
-                bitmap.put(bit, &mut hint);
-                local_ops += 1;
+            let bit = timed_get(iteration, sample_every, &mut latency, || {
+                bitmap.get(&mut ctx)
+            });
+            iteration += 1;
+            match bit {
+                Some(bit) => {
+                    bitmap.put(bit, &mut ctx);
+                    local_ops += 1;
+                }
+                None => failed += 1,
             }
         }
 
-        ops_counter.fetch_add(local_ops, Ordering::Relaxed);
-    });
+        bitmap.drop_ctx(ctx);
+
+        TaskResult {
+            ops: local_ops,
+            failed,
+            consumer: false,
+            latency,
+        }
+    })
 }
 
 /// Run benchmark workload with batch operations: continuous get_batch() and put_batch() operations
 fn run_batch_workload(
     bitmap: Arc<Sbitmap>,
     duration: Duration,
-    ops_counter: Arc<AtomicU64>,
     depth: usize,
     batch_size: usize,
-) {
+    sample_every: u64,
+    cpus: Option<Vec<usize>>,
+) -> JoinHandle<TaskResult> {
     thread::spawn(move || {
+        bind_current_thread(cpus.as_deref());
         let start = Instant::now();
         let mut local_ops = 0u64;
+        let mut failed = 0u64;
+        let mut iteration = 0u64;
+        let mut latency = LatencyHistogram::new();
         let mut hint = init_hint(depth);
 
         while start.elapsed() < duration {
             // One operation = get_batch() + put_batch()
-            if let Some(bit) = bitmap.get_batch(batch_size, &mut hint) {
-                bitmap.put_batch(bit, batch_size, &mut hint);
-                local_ops += 1;
+            let bit = timed_get(iteration, sample_every, &mut latency, || {
+                bitmap.get_batch(batch_size, &mut hint)
+            });
+            iteration += 1;
+            match bit {
+                Some(bit) => {
+                    bitmap.put_batch(bit, batch_size, &mut hint);
+                    local_ops += 1;
+                }
+                None => failed += 1,
+            }
+        }
+
+        TaskResult {
+            ops: local_ops,
+            failed,
+            consumer: false,
+            latency,
+        }
+    })
+}
+
+/// Allocation pattern for the hold/occupancy/producer-consumer workloads
+///
+/// The default benchmark frees every allocation right after getting it, so
+/// the map is almost always empty. A workload instead keeps allocations
+/// around the way real IO holds tags while a request is in flight.
+#[derive(Clone)]
+struct Workload {
+    /// Minimum time an allocation is held before it is freed
+    hold: Duration,
+    /// Target share of the bitmap kept allocated, in percent
+    occupancy: Option<f64>,
+    /// Batch sizes to allocate and their relative weights
+    batch_mix: Vec<(usize, u32)>,
+    /// Number of tasks that only free bits allocated by the other tasks
+    consumers: usize,
+}
+
+impl Workload {
+    /// Mean number of bits per allocation
+    fn mean_batch(&self) -> f64 {
+        let total: u32 = self.batch_mix.iter().map(|&(_, w)| w).sum();
+        let sum: f64 = self
+            .batch_mix
+            .iter()
+            .map(|&(nr, w)| nr as f64 * w as f64)
+            .sum();
+        sum / total as f64
+    }
+
+    /// Largest batch size in the mix
+    fn max_batch(&self) -> usize {
+        self.batch_mix.iter().map(|&(nr, _)| nr).max().unwrap_or(1)
+    }
+
+    /// Allocations each of `holders` keeps in flight to reach the occupancy
+    ///
+    /// Without an occupancy target every holder keeps one allocation.
+    fn in_flight(&self, depth: usize, holders: usize) -> usize {
+        match self.occupancy {
+            Some(pct) => {
+                let bits = depth as f64 * pct / 100.0;
+                ((bits / self.mean_batch() / holders as f64).round() as usize).max(1)
+            }
+            None => 1,
+        }
+    }
+
+    /// Format the batch mix the way --batch-mix accepts it
+    fn format_batch_mix(&self) -> String {
+        let parts: Vec<String> = self
+            .batch_mix
+            .iter()
+            .map(|&(nr, w)| format!("{}:{}", nr, w))
+            .collect();
+        parts.join(",")
+    }
+
+    /// Human-readable summary of the workload
+    fn describe(&self) -> String {
+        let mut parts = vec![format!("hold {}ns", self.hold.as_nanos())];
+        if let Some(pct) = self.occupancy {
+            parts.push(format!("occupancy {}%", pct));
+        }
+        match self.batch_mix.as_slice() {
+            [(nr, _)] => parts.push(format!("batch size {}", nr)),
+            _ => parts.push(format!("batch mix {}", self.format_batch_mix())),
+        }
+        if self.consumers > 0 {
+            parts.push(format!("{} consumer task(s)", self.consumers));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Parse a batch mix such as "1:70,4:20,8:10" into (size, weight) pairs
+///
+/// A size without a weight gets weight 1.
+fn parse_batch_mix(mix: &str) -> Result<Vec<(usize, u32)>, String> {
+    let mut entries = Vec::new();
+    for part in mix.trim().split(',').filter(|p| !p.is_empty()) {
+        let (nr, weight) = part.split_once(':').unwrap_or((part, "1"));
+        let nr = nr
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("invalid batch size '{}'", nr))?;
+        let weight = weight
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| format!("invalid weight '{}'", weight))?;
+        if nr == 0 {
+            return Err("batch size must be at least 1".to_string());
+        }
+        if weight > 0 {
+            entries.push((nr, weight));
+        }
+    }
+    if entries.is_empty() {
+        return Err("empty batch mix".to_string());
+    }
+    Ok(entries)
+}
+
+/// Weighted random choice of batch sizes for one task
+struct BatchPicker {
+    sizes: Vec<usize>,
+    cumulative: Vec<u32>,
+    state: u64,
+}
+
+impl BatchPicker {
+    fn new(mix: &[(usize, u32)], seed: u64) -> Self {
+        let mut total = 0;
+        let cumulative = mix
+            .iter()
+            .map(|&(_, w)| {
+                total += w;
+                total
+            })
+            .collect();
+        Self {
+            sizes: mix.iter().map(|&(nr, _)| nr).collect(),
+            cumulative,
+            state: seed | 1,
+        }
+    }
+
+    /// Pick the next batch size (xorshift64, cheap enough for the hot loop)
+    #[inline]
+    fn pick(&mut self) -> usize {
+        if self.sizes.len() == 1 {
+            return self.sizes[0];
+        }
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        let total = self.cumulative[self.cumulative.len() - 1];
+        let r = (self.state % total as u64) as u32;
+        let i = self.cumulative.partition_point(|&c| c <= r);
+        self.sizes[i]
+    }
+}
+
+/// Busy-wait until `deadline`
+///
+/// Hold times are in the microsecond range, well below what sleeping can
+/// resolve.
+#[inline]
+fn spin_until(deadline: Instant) {
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+/// An allocation in flight: start bit, number of bits, and when it was made
+type Held = (usize, usize, Instant);
+
+/// Run a workload that holds its own allocations before freeing them
+///
+/// The task keeps up to `in_flight` allocations. Once it has that many, it
+/// frees the oldest (after it has been held for `hold`) before allocating
+/// again, which keeps the map at the target occupancy.
+fn run_holding_workload<B>(
+    bitmap: Arc<B>,
+    duration: Duration,
+    sample_every: u64,
+    workload: Workload,
+    in_flight: usize,
+    cpus: Option<Vec<usize>>,
+) -> JoinHandle<TaskResult>
+where
+    B: Send + Sync + 'static,
+    B: BitmapOps,
+{
+    thread::spawn(move || {
+        bind_current_thread(cpus.as_deref());
+        let start = Instant::now();
+        let mut local_ops = 0u64;
+        let mut failed = 0u64;
+        let mut iteration = 0u64;
+        let mut latency = LatencyHistogram::new();
+        let mut ctx = bitmap.new_ctx();
+        let mut picker = BatchPicker::new(&workload.batch_mix, init_hint(usize::MAX) as u64);
+        let timestamps = !workload.hold.is_zero();
+        let mut held: VecDeque<Held> = VecDeque::with_capacity(in_flight);
+
+        while start.elapsed() < duration {
+            if held.len() >= in_flight {
+                let (bit, nr, at) = held.pop_front().unwrap();
+                if timestamps {
+                    spin_until(at + workload.hold);
+                }
+                bitmap.put_batch(bit, nr, &mut ctx);
+            }
+
+            let nr = picker.pick();
+            let bit = timed_get(iteration, sample_every, &mut latency, || {
+                bitmap.get_batch(nr, &mut ctx)
+            });
+            iteration += 1;
+            match bit {
+                Some(bit) => {
+                    let at = if timestamps { Instant::now() } else { start };
+                    held.push_back((bit, nr, at));
+                    local_ops += 1;
+                }
+                None => failed += 1,
             }
         }
 
-        ops_counter.fetch_add(local_ops, Ordering::Relaxed);
-    });
+        for (bit, nr, _) in held {
+            bitmap.put_batch(bit, nr, &mut ctx);
+        }
+
+        bitmap.drop_ctx(ctx);
+
+        TaskResult {
+            ops: local_ops,
+            failed,
+            consumer: false,
+            latency,
+        }
+    })
+}
+
+/// Run a producer: allocate bits and hand them to a consumer task
+///
+/// Sending blocks while the consumer's queue is full, so the queue size
+/// bounds how many allocations are in flight.
+fn run_producer<B>(
+    bitmap: Arc<B>,
+    duration: Duration,
+    sample_every: u64,
+    workload: Workload,
+    tx: SyncSender<Held>,
+    cpus: Option<Vec<usize>>,
+) -> JoinHandle<TaskResult>
+where
+    B: Send + Sync + 'static,
+    B: BitmapOps,
+{
+    thread::spawn(move || {
+        bind_current_thread(cpus.as_deref());
+        let start = Instant::now();
+        let mut local_ops = 0u64;
+        let mut failed = 0u64;
+        let mut iteration = 0u64;
+        let mut latency = LatencyHistogram::new();
+        let mut ctx = bitmap.new_ctx();
+        let mut picker = BatchPicker::new(&workload.batch_mix, init_hint(usize::MAX) as u64);
+        let timestamps = !workload.hold.is_zero();
+
+        while start.elapsed() < duration {
+            let nr = picker.pick();
+            let bit = timed_get(iteration, sample_every, &mut latency, || {
+                bitmap.get_batch(nr, &mut ctx)
+            });
+            iteration += 1;
+            match bit {
+                Some(bit) => {
+                    let at = if timestamps { Instant::now() } else { start };
+                    if tx.send((bit, nr, at)).is_err() {
+                        bitmap.put_batch(bit, nr, &mut ctx);
+                        break;
+                    }
+                    local_ops += 1;
+                }
+                None => failed += 1,
+            }
+        }
+
+        bitmap.drop_ctx(ctx);
+
+        TaskResult {
+            ops: local_ops,
+            failed,
+            consumer: false,
+            latency,
+        }
+    })
+}
+
+/// Run a consumer: free bits allocated by producer tasks
+///
+/// Each allocation is freed once it has been held for `hold`. The task
+/// exits when every producer feeding it has finished and its queue is
+/// drained, so no bits are left allocated.
+fn run_consumer<B>(
+    bitmap: Arc<B>,
+    workload: Workload,
+    rx: Receiver<Held>,
+    cpus: Option<Vec<usize>>,
+) -> JoinHandle<TaskResult>
+where
+    B: Send + Sync + 'static,
+    B: BitmapOps,
+{
+    thread::spawn(move || {
+        bind_current_thread(cpus.as_deref());
+        let mut frees = 0u64;
+        let mut ctx = bitmap.new_ctx();
+        let timestamps = !workload.hold.is_zero();
+
+        for (bit, nr, at) in rx {
+            if timestamps {
+                spin_until(at + workload.hold);
+            }
+            bitmap.put_batch(bit, nr, &mut ctx);
+            frees += 1;
+        }
+
+        bitmap.drop_ctx(ctx);
+
+        TaskResult {
+            ops: frees,
+            failed: 0,
+            consumer: true,
+            latency: LatencyHistogram::new(),
+        }
+    })
 }
 
 /// Trait for bitmap operations to allow generic benchmarking
+///
+/// Each task creates its own `Ctx` and passes it to every call: the
+/// allocation hint for Sbitmap, or a local tag cache for CachedPool.
 trait BitmapOps {
-    fn get(&self, hint: &mut usize) -> Option<usize>;
-    fn put(&self, bitnr: usize, hint: &mut usize);
+    /// Per-task allocation state
+    type Ctx;
+
+    /// Create the state for a new task
+    fn new_ctx(&self) -> Self::Ctx;
+
+    /// Give back anything a finished task still holds in its state
+    fn drop_ctx(&self, _ctx: Self::Ctx) {}
+
+    fn get(&self, ctx: &mut Self::Ctx) -> Option<usize>;
+    fn put(&self, bitnr: usize, ctx: &mut Self::Ctx);
+
+    /// Allocate nr_bits consecutive bits; only single bits by default
+    fn get_batch(&self, nr_bits: usize, ctx: &mut Self::Ctx) -> Option<usize> {
+        if nr_bits == 1 {
+            self.get(ctx)
+        } else {
+            None
+        }
+    }
+
+    /// Free nr_bits consecutive bits; only single bits by default
+    fn put_batch(&self, bitnr: usize, nr_bits: usize, ctx: &mut Self::Ctx) {
+        if nr_bits == 1 {
+            self.put(bitnr, ctx)
+        }
+    }
 }
 
 impl BitmapOps for Sbitmap {
+    type Ctx = usize;
+
+    fn new_ctx(&self) -> usize {
+        init_hint(self.depth())
+    }
+
     fn get(&self, hint: &mut usize) -> Option<usize> {
         Sbitmap::get(self, hint)
     }
@@ -154,48 +682,865 @@ impl BitmapOps for Sbitmap {
     fn put(&self, bitnr: usize, hint: &mut usize) {
         Sbitmap::put(self, bitnr, hint)
     }
+
+    fn get_batch(&self, nr_bits: usize, hint: &mut usize) -> Option<usize> {
+        Sbitmap::get_batch(self, nr_bits, hint)
+    }
+
+    fn put_batch(&self, bitnr: usize, nr_bits: usize, hint: &mut usize) {
+        Sbitmap::put_batch(self, bitnr, nr_bits, hint)
+    }
 }
 
 impl BitmapOps for SimpleBitmap {
-    fn get(&self, _hint: &mut usize) -> Option<usize> {
+    type Ctx = ();
+
+    fn new_ctx(&self) {}
+
+    fn get(&self, _ctx: &mut ()) -> Option<usize> {
         SimpleBitmap::get(self)
     }
 
-    fn put(&self, bitnr: usize, _hint: &mut usize) {
+    fn put(&self, bitnr: usize, _ctx: &mut ()) {
         SimpleBitmap::put(self, bitnr)
     }
 }
 
-/// Detect number of NUMA nodes by reading /sys/devices/system/node/ (Linux only)
+/// Bitmap of bools behind a single mutex
+///
+/// The textbook locked allocator: every get() and put() serializes on one
+/// lock and get() scans from bit 0.
+struct MutexBitmap {
+    bits: Mutex<Vec<bool>>,
+}
+
+impl MutexBitmap {
+    fn new(depth: usize) -> Self {
+        Self {
+            bits: Mutex::new(vec![false; depth]),
+        }
+    }
+}
+
+impl BitmapOps for MutexBitmap {
+    type Ctx = ();
+
+    fn new_ctx(&self) {}
+
+    fn get(&self, _ctx: &mut ()) -> Option<usize> {
+        let mut bits = self.bits.lock().unwrap();
+        let bitnr = bits.iter().position(|&b| !b)?;
+        bits[bitnr] = true;
+        Some(bitnr)
+    }
+
+    fn put(&self, bitnr: usize, _ctx: &mut ()) {
+        if let Some(bit) = self.bits.lock().unwrap().get_mut(bitnr) {
+            *bit = false;
+        }
+    }
+}
+
+/// An atomic index on its own cache line
+#[repr(align(64))]
+struct PaddedIndex(AtomicUsize);
+
+/// One slot of FreeListQueue
+struct QueueSlot {
+    /// Sequence number telling producers and consumers whose turn it is
+    seq: AtomicUsize,
+    tag: AtomicUsize,
+}
+
+/// Bounded lock-free MPMC queue holding the free tag ids
+///
+/// This is Dmitry Vyukov's bounded queue: each slot carries a sequence
+/// number, so push and pop each need one CAS on the tail or head index.
+/// get() pops a free tag and put() pushes it back; the queue starts with
+/// every tag in it.
+///
+/// A push preempted between claiming its slot and publishing the tag
+/// blocks pops from reaching that slot, so with more tasks than CPUs get()
+/// can report failures while free tags are still queued.
+struct FreeListQueue {
+    depth: usize,
+    mask: usize,
+    slots: Box<[QueueSlot]>,
+    head: PaddedIndex,
+    tail: PaddedIndex,
+}
+
+impl FreeListQueue {
+    fn new(depth: usize) -> Self {
+        // Twice the tags, so a push only finds its slot busy when a pop
+        // of that slot a whole lap earlier has not finished yet
+        let capacity = (2 * depth).next_power_of_two();
+        let slots = (0..capacity)
+            .map(|i| QueueSlot {
+                seq: AtomicUsize::new(i),
+                tag: AtomicUsize::new(0),
+            })
+            .collect();
+        let queue = Self {
+            depth,
+            mask: capacity - 1,
+            slots,
+            head: PaddedIndex(AtomicUsize::new(0)),
+            tail: PaddedIndex(AtomicUsize::new(0)),
+        };
+        for tag in 0..depth {
+            queue.push(tag);
+        }
+        queue
+    }
+
+    fn push(&self, tag: usize) -> bool {
+        let mut pos = self.tail.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+            match (seq as isize).wrapping_sub(pos as isize) {
+                0 => match self.tail.0.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        slot.tag.store(tag, Ordering::Relaxed);
+                        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                        return true;
+                    }
+                    Err(actual) => pos = actual,
+                },
+                // Slot still holds an unconsumed tag: the queue is full
+                d if d < 0 => return false,
+                _ => pos = self.tail.0.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<usize> {
+        let mut pos = self.head.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+            match (seq as isize).wrapping_sub(pos.wrapping_add(1) as isize) {
+                0 => match self.head.0.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let tag = slot.tag.load(Ordering::Relaxed);
+                        slot.seq
+                            .store(pos.wrapping_add(self.mask + 1), Ordering::Release);
+                        return Some(tag);
+                    }
+                    Err(actual) => pos = actual,
+                },
+                // Slot not yet filled: the queue is empty
+                d if d < 0 => return None,
+                _ => pos = self.head.0.load(Ordering::Relaxed),
+            }
+        }
+    }
+}
+
+impl BitmapOps for FreeListQueue {
+    type Ctx = ();
+
+    fn new_ctx(&self) {}
+
+    fn get(&self, _ctx: &mut ()) -> Option<usize> {
+        self.pop()
+    }
+
+    fn put(&self, bitnr: usize, _ctx: &mut ()) {
+        if bitnr >= self.depth {
+            return;
+        }
+        // There are never more tags than slots, so a full queue only means
+        // a preempted pop still owns the slot; wait for it instead of
+        // dropping the tag
+        while !self.push(bitnr) {
+            thread::yield_now();
+        }
+    }
+}
+
+/// Per-task tag caches refilled from and flushed to a global pool
+///
+/// Tasks allocate from a private cache and only take the pool lock to move
+/// `batch` tags at a time, like a slab magazine. Tags sitting in another
+/// task's cache cannot be allocated, so get() can fail before the map is
+/// really full.
+struct CachedPool {
+    pool: Mutex<Vec<usize>>,
+    batch: usize,
+}
+
+impl CachedPool {
+    /// Split `depth` tags into a pool shared by `num_tasks` task caches
+    ///
+    /// Each cache holds at most 2 * batch tags, and batch is kept small
+    /// enough that the caches together can only claim half of the tags.
+    fn new(depth: usize, num_tasks: usize) -> Self {
+        Self {
+            pool: Mutex::new((0..depth).rev().collect()),
+            batch: (depth / (4 * num_tasks.max(1))).clamp(1, 32),
+        }
+    }
+}
+
+impl BitmapOps for CachedPool {
+    type Ctx = Vec<usize>;
+
+    fn new_ctx(&self) -> Vec<usize> {
+        Vec::with_capacity(2 * self.batch)
+    }
+
+    fn drop_ctx(&self, cache: Vec<usize>) {
+        self.pool.lock().unwrap().extend(cache);
+    }
+
+    fn get(&self, cache: &mut Vec<usize>) -> Option<usize> {
+        if cache.is_empty() {
+            let mut pool = self.pool.lock().unwrap();
+            let take = pool.len().min(self.batch);
+            let start = pool.len() - take;
+            cache.extend(pool.drain(start..));
+        }
+        cache.pop()
+    }
+
+    fn put(&self, bitnr: usize, cache: &mut Vec<usize>) {
+        cache.push(bitnr);
+        if cache.len() >= 2 * self.batch {
+            let start = cache.len() - self.batch;
+            self.pool.lock().unwrap().extend(cache.drain(start..));
+        }
+    }
+}
+
+/// Baseline allocators bench_compare can compare Sbitmap against
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Baseline {
+    /// SimpleBitmap: lock-free bitmap without hints or alignment
+    Simple,
+    /// MutexBitmap: Mutex<Vec<bool>>
+    Mutex,
+    /// FreeListQueue: lock-free MPMC queue of free tags
+    Queue,
+    /// CachedPool: per-task caches over a locked global pool
+    Cache,
+}
+
+impl Baseline {
+    const ALL: [Baseline; 4] = [
+        Baseline::Simple,
+        Baseline::Mutex,
+        Baseline::Queue,
+        Baseline::Cache,
+    ];
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "simple" => Some(Self::Simple),
+            "mutex" => Some(Self::Mutex),
+            "queue" => Some(Self::Queue),
+            "cache" => Some(Self::Cache),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Simple => "SimpleBitmap (Baseline)",
+            Self::Mutex => "Mutex<Vec<bool>>",
+            Self::Queue => "Lock-free queue",
+            Self::Cache => "Per-task cache",
+        }
+    }
+
+    /// Build this baseline and benchmark it
+    fn run(self, params: &BenchParams, workload: Option<&Workload>) -> BenchResult {
+        let depth = params.depth;
+        match self {
+            Self::Simple => single_bit_benchmark(
+                self.name(),
+                Arc::new(SimpleBitmap::new(depth)),
+                params,
+                workload,
+            ),
+            Self::Mutex => single_bit_benchmark(
+                self.name(),
+                Arc::new(MutexBitmap::new(depth)),
+                params,
+                workload,
+            ),
+            Self::Queue => single_bit_benchmark(
+                self.name(),
+                Arc::new(FreeListQueue::new(depth)),
+                params,
+                workload,
+            ),
+            Self::Cache => single_bit_benchmark(
+                self.name(),
+                Arc::new(CachedPool::new(depth, params.num_tasks)),
+                params,
+                workload,
+            ),
+        }
+    }
+}
+
+/// Parse a list such as "0-3,8,10-11" into individual numbers
+///
+/// `what` names the values in error messages.
+fn parse_list(list: &str, what: &str) -> Result<Vec<usize>, String> {
+    let mut values = Vec::new();
+    for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid {} '{}'", what, v))
+        };
+        match part.split_once('-') {
+            Some((lo, hi)) => {
+                let (lo, hi) = (parse(lo)?, parse(hi)?);
+                if lo > hi {
+                    return Err(format!("invalid {} range '{}'", what, part));
+                }
+                values.extend(lo..=hi);
+            }
+            None => values.push(parse(part)?),
+        }
+    }
+    if values.is_empty() {
+        return Err(format!("empty {} list", what));
+    }
+    Ok(values)
+}
+
+/// Parse a CPU list such as "0-3,8,10-11" into individual CPU numbers
+fn parse_cpu_list(list: &str) -> Result<Vec<usize>, String> {
+    parse_list(list, "CPU")
+}
+
+/// Format CPU numbers compactly, e.g. [0, 1, 2, 3, 8] as "0-3,8"
+fn format_cpu_list(cpus: &[usize]) -> String {
+    let mut parts = Vec::new();
+    let mut i = 0;
+    while i < cpus.len() {
+        let mut j = i;
+        while j + 1 < cpus.len() && cpus[j + 1] == cpus[j] + 1 {
+            j += 1;
+        }
+        if j > i {
+            parts.push(format!("{}-{}", cpus[i], cpus[j]));
+        } else {
+            parts.push(cpus[i].to_string());
+        }
+        i = j + 1;
+    }
+    parts.join(",")
+}
+
+/// Detect NUMA topology by reading /sys/devices/system/node/ (Linux only)
+///
+/// Returns the CPUs of each node, ordered by node number. Falls back to a
+/// single node holding every CPU if sysfs is unavailable.
+#[cfg(target_os = "linux")]
+fn detect_numa_topology(total_cpus: usize) -> Vec<Vec<usize>> {
+    let mut nodes: Vec<(usize, Vec<usize>)> = match fs::read_dir("/sys/devices/system/node") {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name().to_string_lossy().into_owned();
+                let id = name.strip_prefix("node")?.parse::<usize>().ok()?;
+                let cpulist = fs::read_to_string(e.path().join("cpulist")).ok()?;
+                // Memory-only nodes have an empty cpulist
+                let cpus = parse_cpu_list(&cpulist).ok()?;
+                Some((id, cpus))
+            })
+            .collect(),
+        Err(_) => Vec::new(), // Default to 1 node if we can't detect
+    };
+    nodes.sort();
+
+    if nodes.is_empty() {
+        vec![(0..total_cpus).collect()]
+    } else {
+        nodes.into_iter().map(|(_, cpus)| cpus).collect()
+    }
+}
+
+/// Detect NUMA topology (non-Linux platforms)
+#[cfg(not(target_os = "linux"))]
+fn detect_numa_topology(total_cpus: usize) -> Vec<Vec<usize>> {
+    vec![(0..total_cpus).collect()] // Default to 1 node on non-Linux platforms
+}
+
+/// Number of CPUs in glibc's cpu_set_t (CPU_SETSIZE)
 #[cfg(target_os = "linux")]
-fn detect_numa_nodes() -> usize {
-    match fs::read_dir("/sys/devices/system/node") {
-        Ok(entries) => {
-            let count = entries
-                .filter_map(|e| e.ok())
-                .filter(|e| {
-                    e.file_name().to_string_lossy().starts_with("node")
-                        && e.file_name()
-                            .to_string_lossy()
-                            .chars()
-                            .skip(4)
-                            .all(|c| c.is_ascii_digit())
+const CPU_SETSIZE: usize = 1024;
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const u64) -> i32;
+}
+
+/// Restrict the calling thread to the given CPUs with sched_setaffinity()
+#[cfg(target_os = "linux")]
+fn set_thread_affinity(cpus: &[usize]) -> Result<(), String> {
+    let mut mask = [0u64; CPU_SETSIZE / 64];
+    for &cpu in cpus {
+        if cpu >= CPU_SETSIZE {
+            return Err(format!("CPU {} exceeds CPU_SETSIZE", cpu));
+        }
+        mask[cpu / 64] |= 1u64 << (cpu % 64);
+    }
+
+    // SAFETY: mask is a valid cpu_set_t-sized buffer; pid 0 is the calling thread
+    let ret = unsafe { sched_setaffinity(0, std::mem::size_of_val(&mask), mask.as_ptr()) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(())
+}
+
+/// Restrict the calling thread to the given CPUs (unsupported on this platform)
+#[cfg(not(target_os = "linux"))]
+fn set_thread_affinity(_cpus: &[usize]) -> Result<(), String> {
+    Err("CPU affinity is only supported on Linux".to_string())
+}
+
+/// Apply a task's CPU placement to the calling thread, if it has one
+fn bind_current_thread(cpus: Option<&[usize]>) {
+    if let Some(cpus) = cpus {
+        if let Err(e) = set_thread_affinity(cpus) {
+            eprintln!(
+                "Warning: failed to bind task to CPUs {}: {}",
+                format_cpu_list(cpus),
+                e
+            );
+        }
+    }
+}
+
+/// How tasks are placed across NUMA nodes
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NumaPolicy {
+    /// No node preference
+    Any,
+    /// Round-robin tasks across nodes
+    Spread,
+    /// Keep every task on a single node
+    Local,
+}
+
+/// CPU placement of benchmark tasks
+struct Placement {
+    /// CPUs each task is restricted to; None leaves it to the OS scheduler
+    task_cpus: Vec<Option<Vec<usize>>>,
+    /// Human-readable summary of the placement
+    description: String,
+}
+
+impl Placement {
+    /// Decide which CPUs each task may run on
+    ///
+    /// Only CPUs in `allowed` (all CPUs if None) are used. With `pin`, every
+    /// task is bound to a single CPU; otherwise a task may float within its
+    /// node (NUMA policy) or within the allowed set.
+    fn plan(
+        num_tasks: usize,
+        allowed: Option<&[usize]>,
+        pin: bool,
+        numa: NumaPolicy,
+        topology: &[Vec<usize>],
+    ) -> Self {
+        let all: Vec<usize> = topology.iter().flatten().copied().collect();
+        let restricted_cpus = allowed.is_some();
+        let allowed: Vec<usize> = allowed.map_or_else(|| all.clone(), |list| list.to_vec());
+
+        // Allowed CPUs grouped by node, dropping nodes with none of them.
+        // CPUs outside the detected topology are treated as one extra node.
+        let mut nodes: Vec<Vec<usize>> = topology
+            .iter()
+            .map(|node| {
+                node.iter()
+                    .copied()
+                    .filter(|c| allowed.contains(c))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|node| !node.is_empty())
+            .collect();
+        let stray: Vec<usize> = allowed
+            .iter()
+            .copied()
+            .filter(|c| !all.contains(c))
+            .collect();
+        if !stray.is_empty() {
+            nodes.push(stray);
+        }
+        let nr_nodes = nodes.len();
+
+        // Groups of CPUs that tasks are assigned to in turn
+        let groups: Vec<Vec<usize>> = match numa {
+            NumaPolicy::Any => vec![allowed.clone()],
+            NumaPolicy::Spread => nodes,
+            NumaPolicy::Local => vec![nodes.swap_remove(0)],
+        };
+
+        let restricted = pin || numa != NumaPolicy::Any || restricted_cpus;
+        let task_cpus = (0..num_tasks)
+            .map(|i| {
+                if !restricted {
+                    return None;
+                }
+                let group = &groups[i % groups.len()];
+                let slot = i / groups.len();
+                Some(if pin {
+                    vec![group[slot % group.len()]]
+                } else {
+                    group.clone()
                 })
-                .count();
-            if count > 0 {
-                count
-            } else {
-                1
+            })
+            .collect();
+
+        let mut description = if pin {
+            "pinned, one CPU per task".to_string()
+        } else if restricted {
+            "unpinned, restricted CPU set".to_string()
+        } else {
+            "unpinned (OS scheduler)".to_string()
+        };
+        match numa {
+            NumaPolicy::Any => {}
+            NumaPolicy::Spread => description.push_str(&format!(
+                ", spread across {} NUMA node{}",
+                nr_nodes,
+                if nr_nodes == 1 { "" } else { "s" }
+            )),
+            NumaPolicy::Local => description.push_str(", NUMA-local (single node)"),
+        }
+        if restricted_cpus {
+            description.push_str(&format!(", CPUs {}", format_cpu_list(&allowed)));
+        }
+
+        Self {
+            task_cpus,
+            description,
+        }
+    }
+
+    /// CPUs the given task is restricted to, if any
+    fn cpus(&self, task: usize) -> Option<Vec<usize>> {
+        self.task_cpus[task].clone()
+    }
+
+    /// Print the placement and each task's CPUs
+    fn print(&self, topology: &[Vec<usize>]) {
+        println!("Placement: {}", self.description);
+        for (i, cpus) in self.task_cpus.iter().enumerate() {
+            if let Some(cpus) = cpus {
+                let nodes: Vec<String> = topology
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, node)| cpus.iter().any(|c| node.contains(c)))
+                    .map(|(n, _)| n.to_string())
+                    .collect();
+                println!(
+                    "  Task {} -> CPU{} {} (node {})",
+                    i,
+                    if cpus.len() == 1 { "" } else { "s" },
+                    format_cpu_list(cpus),
+                    if nodes.is_empty() {
+                        "?".to_string()
+                    } else {
+                        nodes.join(",")
+                    }
+                );
             }
         }
-        Err(_) => 1, // Default to 1 if we can't detect
     }
 }
 
-/// Detect number of NUMA nodes (non-Linux platforms)
-#[cfg(not(target_os = "linux"))]
-fn detect_numa_nodes() -> usize {
-    1 // Default to 1 on non-Linux platforms
+/// Parameters shared by every benchmark run
+struct BenchParams {
+    duration: Duration,
+    depth: usize,
+    num_tasks: usize,
+    sample_every: u64,
+    placement: Placement,
+    /// Print the configuration and per-task results of each run
+    verbose: bool,
+}
+
+/// How results are written to stdout
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// Human-readable report
+    Text,
+    /// A single JSON document
+    Json,
+    /// A header line plus one row per implementation
+    Csv,
+}
+
+impl OutputFormat {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// Benchmark configuration recorded alongside machine-readable results
+struct RunConfig {
+    depth: usize,
+    shift: Option<u32>,
+    bits_per_word: usize,
+    tasks: usize,
+    batch: usize,
+    round_robin: bool,
+    duration_secs: u64,
+    sample_every: u64,
+    cpus: usize,
+    numa_nodes: usize,
+    placement: String,
+    /// None for the default back-to-back get/put loop
+    workload: Option<Workload>,
+}
+
+/// Throughput of `result` relative to `baseline`
+fn speedup(result: &BenchResult, baseline: &BenchResult) -> f64 {
+    if baseline.ops_per_sec > 0.0 {
+        result.ops_per_sec / baseline.ops_per_sec
+    } else {
+        0.0
+    }
+}
+
+/// Quote a string for JSON output
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Quote a CSV field if it contains a separator, quote or newline
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Format the configuration and results as one JSON document
+///
+/// `baseline` indexes the result the others are compared against, if any.
+fn json_report(config: &RunConfig, results: &[BenchResult], baseline: Option<usize>) -> String {
+    let mut lines = Vec::new();
+    lines.push("{".to_string());
+    lines.push("  \"config\": {".to_string());
+    lines.push(format!("    \"depth\": {},", config.depth));
+    lines.push(format!(
+        "    \"shift\": {},",
+        config.shift.map_or("null".to_string(), |s| s.to_string())
+    ));
+    lines.push(format!("    \"bits_per_word\": {},", config.bits_per_word));
+    lines.push(format!("    \"tasks\": {},", config.tasks));
+    lines.push(format!("    \"batch\": {},", config.batch));
+    lines.push(format!("    \"round_robin\": {},", config.round_robin));
+    lines.push(format!("    \"duration_secs\": {},", config.duration_secs));
+    lines.push(format!("    \"sample_every\": {},", config.sample_every));
+    lines.push(format!("    \"cpus\": {},", config.cpus));
+    lines.push(format!("    \"numa_nodes\": {},", config.numa_nodes));
+    lines.push(format!(
+        "    \"placement\": {},",
+        json_string(&config.placement)
+    ));
+    match &config.workload {
+        Some(w) => lines.push(format!(
+            "    \"workload\": {{ \"hold_ns\": {}, \"occupancy_pct\": {}, \"batch_mix\": {}, \"consumers\": {} }}",
+            w.hold.as_nanos(),
+            w.occupancy.map_or("null".to_string(), |o| o.to_string()),
+            json_string(&w.format_batch_mix()),
+            w.consumers
+        )),
+        None => lines.push("    \"workload\": null".to_string()),
+    }
+    lines.push("  },".to_string());
+
+    lines.push("  \"results\": [".to_string());
+    for (i, result) in results.iter().enumerate() {
+        let task_ops: Vec<String> = result.task_ops.iter().map(|o| o.to_string()).collect();
+        lines.push("    {".to_string());
+        lines.push(format!("      \"name\": {},", json_string(&result.name)));
+        let consumer_frees: Vec<String> = result
+            .consumer_frees
+            .iter()
+            .map(|o| o.to_string())
+            .collect();
+        lines.push(format!("      \"task_ops\": [{}],", task_ops.join(", ")));
+        lines.push(format!(
+            "      \"consumer_frees\": [{}],",
+            consumer_frees.join(", ")
+        ));
+        lines.push(format!("      \"total_ops\": {},", result.total_ops));
+        lines.push(format!("      \"failed_gets\": {},", result.failed_gets));
+        lines.push(format!("      \"ops_per_sec\": {:.3},", result.ops_per_sec));
+        if config.sample_every == 0 {
+            lines.push("      \"latency_ns\": null".to_string());
+        } else {
+            let mut fields: Vec<String> = PERCENTILES
+                .iter()
+                .map(|(label, pct)| format!("\"{}\": {}", label, result.latency.percentile(*pct)))
+                .collect();
+            fields.push(format!("\"max\": {}", result.latency.max));
+            fields.push(format!("\"samples\": {}", result.latency.count));
+            lines.push(format!("      \"latency_ns\": {{ {} }}", fields.join(", ")));
+        }
+        lines.push(format!(
+            "    }}{}",
+            if i + 1 < results.len() { "," } else { "" }
+        ));
+    }
+    lines.push("  ],".to_string());
+
+    match baseline {
+        Some(b) => {
+            let speedups: Vec<String> = results
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != b)
+                .map(|(_, r)| format!("{}: {:.4}", json_string(&r.name), speedup(r, &results[b])))
+                .collect();
+            lines.push("  \"comparison\": {".to_string());
+            lines.push(format!(
+                "    \"baseline\": {},",
+                json_string(&results[b].name)
+            ));
+            lines.push(format!("    \"speedup\": {{ {} }}", speedups.join(", ")));
+            lines.push("  }".to_string());
+        }
+        None => lines.push("  \"comparison\": null".to_string()),
+    }
+    lines.push("}".to_string());
+    lines.join("\n")
+}
+
+/// Print the configuration and results as one JSON document
+fn print_json(config: &RunConfig, results: &[BenchResult], baseline: Option<usize>) {
+    println!("{}", json_report(config, results, baseline));
+}
+
+/// Print one CSV row per implementation, preceded by a header if `header`
+///
+/// Every row repeats the configuration so rows from several runs can be
+/// concatenated into one table. Per-task ops are joined with ';'.
+fn print_csv(config: &RunConfig, results: &[BenchResult], baseline: Option<usize>, header: bool) {
+    let mut columns = vec![
+        "name",
+        "depth",
+        "shift",
+        "bits_per_word",
+        "tasks",
+        "batch",
+        "round_robin",
+        "duration_secs",
+        "sample_every",
+        "cpus",
+        "numa_nodes",
+        "placement",
+        "hold_ns",
+        "occupancy_pct",
+        "batch_mix",
+        "consumers",
+        "total_ops",
+        "failed_gets",
+        "ops_per_sec",
+        "baseline",
+        "speedup",
+    ];
+    columns.extend(PERCENTILES.iter().map(|(label, _)| *label));
+    columns.extend(["max", "samples", "task_ops", "consumer_frees"]);
+    if header {
+        println!("{}", columns.join(","));
+    }
+
+    let workload = config.workload.as_ref();
+    for (i, result) in results.iter().enumerate() {
+        let (baseline_name, speedup) = match baseline {
+            Some(b) if b != i => (
+                results[b].name.clone(),
+                format!("{:.4}", speedup(result, &results[b])),
+            ),
+            _ => (String::new(), String::new()),
+        };
+        let mut row = vec![
+            csv_field(&result.name),
+            config.depth.to_string(),
+            config.shift.map_or(String::new(), |s| s.to_string()),
+            config.bits_per_word.to_string(),
+            config.tasks.to_string(),
+            config.batch.to_string(),
+            config.round_robin.to_string(),
+            config.duration_secs.to_string(),
+            config.sample_every.to_string(),
+            config.cpus.to_string(),
+            config.numa_nodes.to_string(),
+            csv_field(&config.placement),
+            workload.map_or(String::new(), |w| w.hold.as_nanos().to_string()),
+            workload
+                .and_then(|w| w.occupancy)
+                .map_or(String::new(), |o| o.to_string()),
+            workload.map_or(String::new(), |w| csv_field(&w.format_batch_mix())),
+            workload.map_or(String::new(), |w| w.consumers.to_string()),
+            result.total_ops.to_string(),
+            result.failed_gets.to_string(),
+            format!("{:.3}", result.ops_per_sec),
+            csv_field(&baseline_name),
+            speedup,
+        ];
+        if config.sample_every == 0 {
+            row.resize(row.len() + PERCENTILES.len() + 2, String::new());
+        } else {
+            row.extend(
+                PERCENTILES
+                    .iter()
+                    .map(|(_, pct)| result.latency.percentile(*pct).to_string()),
+            );
+            row.push(result.latency.max.to_string());
+            row.push(result.latency.count.to_string());
+        }
+        let task_ops: Vec<String> = result.task_ops.iter().map(|o| o.to_string()).collect();
+        let consumer_frees: Vec<String> = result
+            .consumer_frees
+            .iter()
+            .map(|o| o.to_string())
+            .collect();
+        row.push(task_ops.join(";"));
+        row.push(consumer_frees.join(";"));
+        println!("{}", row.join(","));
+    }
 }
 
 /// Print usage information
@@ -211,6 +1556,25 @@ fn print_usage(program: &str) {
         "  --batch NR_BITS    Use get_batch/put_batch with NR_BITS (default: 1, single bit mode)"
     );
     eprintln!("  --round-robin      Enable round-robin allocation mode (default: disabled)");
+    eprintln!(
+        "  --sample-every N   Time one in N get() calls for latency percentiles (default: 64, 0: off)"
+    );
+    eprintln!("  --cpus LIST        Only run tasks on these CPUs, e.g. 0-3,8 (default: all)");
+    eprintln!("  --pin              Pin each task to a single CPU (default: OS scheduler)");
+    eprintln!("  --numa-spread      Distribute tasks round-robin across NUMA nodes");
+    eprintln!("  --numa-local       Keep all tasks on a single NUMA node");
+    eprintln!("  --format FORMAT    Output format: text, json or csv (default: text)");
+    eprintln!("  --baselines LIST   Baselines to compare: simple,mutex,queue,cache or none (default: all)");
+    eprintln!("  --hold NS          Hold each allocation for NS nanoseconds before freeing it");
+    eprintln!("  --occupancy PCT    Keep about PCT% of the bitmap allocated in steady state");
+    eprintln!(
+        "  --batch-mix MIX    Weighted batch sizes, e.g. 1:70,4:20,8:10 (Sbitmap only if > 1)"
+    );
+    eprintln!("  --consumers N      Last N tasks free the bits the other tasks allocate");
+    eprintln!("  --sweep            Run every combination of the sweep lists below");
+    eprintln!("  --sweep-depths L   Depths to sweep, e.g. 64,256,1024 (default: --depth)");
+    eprintln!("  --sweep-shifts L   Shifts to sweep, e.g. 2-6 (default: --shift, or all)");
+    eprintln!("  --sweep-tasks L    Task counts to sweep, e.g. 1,2,4,8 (default: --tasks)");
     eprintln!("  -h, --help         Show this help message");
     eprintln!();
     eprintln!("Examples:");
@@ -218,104 +1582,239 @@ fn print_usage(program: &str) {
     eprintln!("  {} --depth 512 --shift 5 --time 10", program);
     eprintln!("  {} --depth 256 --tasks 8 --round-robin", program);
     eprintln!("  {} --depth 128 --batch 4", program);
+    eprintln!("  {} --depth 64 --sample-every 1", program);
+    eprintln!("  {} --depth 256 --tasks 8 --pin --numa-spread", program);
+    eprintln!("  {} --depth 256 --cpus 0-7 --pin", program);
+    eprintln!("  {} --depth 1024 --tasks 4 --format json", program);
+    eprintln!(
+        "  {} --depth 1024 --hold 2000 --occupancy 75 --batch-mix 1:70,4:20,8:10",
+        program
+    );
+    eprintln!(
+        "  {} --depth 256 --tasks 4 --consumers 2 --hold 1000",
+        program
+    );
+    eprintln!(
+        "  {} --sweep --sweep-depths 64,1024 --sweep-tasks 1,4 --time 2",
+        program
+    );
 }
 
 /// Run benchmark with N tasks
-fn benchmark<B>(name: &str, bitmap: Arc<B>, duration: Duration, depth: usize, num_tasks: usize)
+fn benchmark<B>(name: &str, bitmap: Arc<B>, params: &BenchParams) -> BenchResult
 where
     B: Send + Sync + 'static + BitmapOps,
 {
+    let (duration, sample_every) = (params.duration, params.sample_every);
     benchmark_internal(
         name,
-        duration,
-        depth,
-        num_tasks,
+        params,
         None,
-        |bitmap_clone, counter| {
-            run_workload(bitmap_clone, duration, counter, depth);
-        },
+        |_, bitmap_clone, cpus| run_workload(bitmap_clone, duration, sample_every, cpus),
         bitmap,
-    );
+    )
 }
 
 /// Run batch benchmark with N tasks (Sbitmap only)
 fn batch_benchmark(
     name: &str,
-    bitmap: Arc<Sbitmap>,
-    duration: Duration,
-    depth: usize,
-    num_tasks: usize,
-    batch_size: usize,
-) {
+    bitmap: Arc<Sbitmap>,
+    params: &BenchParams,
+    batch_size: usize,
+) -> BenchResult {
+    let (duration, depth, sample_every) = (params.duration, params.depth, params.sample_every);
+    benchmark_internal(
+        name,
+        params,
+        Some(batch_size),
+        |_, bitmap_clone, cpus| {
+            run_batch_workload(
+                bitmap_clone,
+                duration,
+                depth,
+                batch_size,
+                sample_every,
+                cpus,
+            )
+        },
+        bitmap,
+    )
+}
+
+/// Benchmark Sbitmap with the back-to-back loop or the given workload
+fn sbitmap_benchmark(
+    name: &str,
+    sbitmap: Arc<Sbitmap>,
+    params: &BenchParams,
+    batch_size: usize,
+    workload: Option<&Workload>,
+) -> BenchResult {
+    match workload {
+        Some(workload) => workload_benchmark(name, sbitmap, params, workload),
+        None if batch_size > 1 => batch_benchmark(name, sbitmap, params, batch_size),
+        None => benchmark(name, sbitmap, params),
+    }
+}
+
+/// Benchmark any allocator with the back-to-back loop or the given workload
+fn single_bit_benchmark<B>(
+    name: &str,
+    bitmap: Arc<B>,
+    params: &BenchParams,
+    workload: Option<&Workload>,
+) -> BenchResult
+where
+    B: Send + Sync + 'static + BitmapOps,
+{
+    match workload {
+        Some(workload) => workload_benchmark(name, bitmap, params, workload),
+        None => benchmark(name, bitmap, params),
+    }
+}
+
+/// Run a hold/occupancy or producer/consumer workload with N tasks
+///
+/// With consumers, the last `workload.consumers` tasks free what the other
+/// tasks allocate; each producer feeds one consumer's bounded queue.
+fn workload_benchmark<B>(
+    name: &str,
+    bitmap: Arc<B>,
+    params: &BenchParams,
+    workload: &Workload,
+) -> BenchResult
+where
+    B: Send + Sync + 'static + BitmapOps,
+{
+    let (duration, depth, sample_every) = (params.duration, params.depth, params.sample_every);
+    let consumers = workload.consumers;
+    let producers = params.num_tasks - consumers;
+
+    // Queues are handed out as tasks are spawned; once every producer has
+    // taken its sender, a consumer's queue disconnects when they all finish.
+    let mut senders = Vec::new();
+    let mut receivers = Vec::new();
+    for _ in 0..consumers {
+        let (tx, rx) = mpsc::sync_channel(workload.in_flight(depth, consumers));
+        senders.push(tx);
+        receivers.push(Some(rx));
+    }
+    let producer_tx: Vec<Option<SyncSender<Held>>> = (0..producers)
+        .map(|p| senders.get(p % consumers.max(1)).cloned())
+        .collect();
+    drop(senders);
+    let producer_tx = RefCell::new(producer_tx);
+    let receivers = RefCell::new(receivers);
+    let in_flight = workload.in_flight(depth, params.num_tasks);
+
     benchmark_internal(
         name,
-        duration,
-        depth,
-        num_tasks,
-        Some(batch_size),
-        |bitmap_clone, counter| {
-            run_batch_workload(bitmap_clone, duration, counter, depth, batch_size);
+        params,
+        None,
+        |task, bitmap_clone, cpus| {
+            let workload = workload.clone();
+            if consumers == 0 {
+                return run_holding_workload(
+                    bitmap_clone,
+                    duration,
+                    sample_every,
+                    workload,
+                    in_flight,
+                    cpus,
+                );
+            }
+            if task < producers {
+                let tx = producer_tx.borrow_mut()[task].take().unwrap();
+                run_producer(bitmap_clone, duration, sample_every, workload, tx, cpus)
+            } else {
+                let rx = receivers.borrow_mut()[task - producers].take().unwrap();
+                run_consumer(bitmap_clone, workload, rx, cpus)
+            }
         },
         bitmap,
-    );
+    )
 }
 
 /// Internal benchmark implementation shared by benchmark() and batch_benchmark()
 fn benchmark_internal<B, F>(
     name: &str,
-    duration: Duration,
-    depth: usize,
-    num_tasks: usize,
+    params: &BenchParams,
     batch_size: Option<usize>,
     spawn_workload: F,
     bitmap: Arc<B>,
-) where
+) -> BenchResult
+where
     B: Send + Sync + 'static,
-    F: Fn(Arc<B>, Arc<AtomicU64>),
+    F: Fn(usize, Arc<B>, Option<Vec<usize>>) -> JoinHandle<TaskResult>,
 {
-    // Print header
-    if batch_size.is_some() {
-        println!("\n=== {} Benchmark (Batch Mode) ===", name);
-    } else {
-        println!("\n=== {} Benchmark ===", name);
-    }
+    let (duration, depth, num_tasks) = (params.duration, params.depth, params.num_tasks);
 
-    println!("Configuration:");
-    println!("  - Duration: {:?}", duration);
-    println!("  - Tasks: {}", num_tasks);
-    println!("  - Bitmap depth: {} bits", depth);
-    if let Some(batch) = batch_size {
-        println!("  - Batch size: {} bits", batch);
-    }
+    // Print header
+    if params.verbose {
+        if batch_size.is_some() {
+            println!("\n=== {} Benchmark (Batch Mode) ===", name);
+        } else {
+            println!("\n=== {} Benchmark ===", name);
+        }
 
-    // Create counter for each task
-    let mut ops_counters = Vec::new();
-    for _ in 0..num_tasks {
-        ops_counters.push(Arc::new(AtomicU64::new(0)));
+        println!("Configuration:");
+        println!("  - Duration: {:?}", duration);
+        println!("  - Tasks: {}", num_tasks);
+        println!("  - Bitmap depth: {} bits", depth);
+        if let Some(batch) = batch_size {
+            println!("  - Batch size: {} bits", batch);
+        }
     }
 
     // Spawn tasks
-    for i in 0..num_tasks {
-        let bitmap_clone = Arc::clone(&bitmap);
-        let counter = Arc::clone(&ops_counters[i]);
-        spawn_workload(bitmap_clone, counter);
-    }
+    let handles: Vec<_> = (0..num_tasks)
+        .map(|i| spawn_workload(i, Arc::clone(&bitmap), params.placement.cpus(i)))
+        .collect();
 
-    // Wait for duration + a bit more for threads to finish
-    thread::sleep(duration + Duration::from_millis(100));
+    // Wait for every task to finish its timed loop
+    let task_results: Vec<TaskResult> = handles
+        .into_iter()
+        .map(|h| h.join().expect("benchmark task panicked"))
+        .collect();
 
     let duration_secs = duration.as_secs_f64();
     let mut total_ops = 0u64;
+    let mut failed_gets = 0u64;
+    let mut latency = LatencyHistogram::new();
 
     // Collect all results first to calculate column widths
     let mut results = Vec::new();
-    for i in 0..num_tasks {
-        let ops = ops_counters[i].load(Ordering::Relaxed);
+    for (i, task) in task_results.iter().enumerate() {
+        let ops = task.ops;
         let ops_per_sec = ops as f64 / duration_secs;
-        results.push((i, ops, ops_per_sec));
-        total_ops += ops;
+        results.push((i, ops, ops_per_sec, task.consumer));
+        // Consumers free what producers allocated; only count allocations
+        if !task.consumer {
+            total_ops += ops;
+        }
+        failed_gets += task.failed;
+        latency.merge(&task.latency);
     }
     let total_ops_per_sec = total_ops as f64 / duration_secs;
+    let ops_of = |consumer: bool| -> Vec<u64> {
+        results
+            .iter()
+            .filter(|r| r.3 == consumer)
+            .map(|r| r.1)
+            .collect()
+    };
+
+    let result = BenchResult {
+        name: name.to_string(),
+        task_ops: ops_of(false),
+        consumer_frees: ops_of(true),
+        total_ops,
+        failed_gets,
+        ops_per_sec: total_ops_per_sec,
+        latency,
+    };
+    if !params.verbose {
+        return result;
+    }
 
     // Calculate column widths for alignment
     let task_width = num_tasks.to_string().len();
@@ -323,13 +1822,21 @@ fn benchmark_internal<B, F>(
     let ops_per_sec_width = (total_ops_per_sec as u64).to_string().len().max(10);
 
     println!("\nResults:");
-    for (i, ops, ops_per_sec) in results {
+    for (i, ops, ops_per_sec, consumer) in results {
+        let mut cpus = match params.placement.cpus(i) {
+            Some(cpus) => format!(" [CPU {}]", format_cpu_list(&cpus)),
+            None => String::new(),
+        };
+        if consumer {
+            cpus.push_str(" (consumer, frees)");
+        }
         println!(
-            "  Task {:>width_task$}: {:>width_ops$} ops, {:>width_ops_sec$} ops/sec ({:>8.3} Mops/sec)",
+            "  Task {:>width_task$}: {:>width_ops$} ops, {:>width_ops_sec$} ops/sec ({:>8.3} Mops/sec){}",
             i,
             ops,
             ops_per_sec as u64,
             ops_per_sec / 1_000_000.0,
+            cpus,
             width_task = task_width,
             width_ops = ops_width,
             width_ops_sec = ops_per_sec_width
@@ -346,6 +1853,252 @@ fn benchmark_internal<B, F>(
         width_ops = ops_width,
         width_ops_sec = ops_per_sec_width
     );
+    if result.failed_gets > 0 {
+        println!("  Failed gets: {} (no free bits)", result.failed_gets);
+    }
+
+    result
+}
+
+/// Print total throughput of each implementation relative to `baseline`
+fn print_throughput_table(results: &[BenchResult], baseline: Option<usize>) {
+    let name_width = results.iter().map(|r| r.name.len()).max().unwrap_or(0);
+
+    println!("\nThroughput:");
+    print!(
+        "  {:<width$} {:>10} {:>12}",
+        "",
+        "Mops/sec",
+        "failed gets",
+        width = name_width
+    );
+    match baseline {
+        Some(b) => println!("   vs {}", results[b].name),
+        None => println!(),
+    }
+    for result in results {
+        print!(
+            "  {:<width$} {:>10.3} {:>12}",
+            result.name,
+            result.ops_per_sec / 1_000_000.0,
+            result.failed_gets,
+            width = name_width
+        );
+        match baseline {
+            Some(b) => println!("   {:>6.2}x", speedup(result, &results[b])),
+            None => println!(),
+        }
+    }
+}
+
+/// Print sampled allocation latency percentiles for each implementation side by side
+fn print_latency_table(results: &[BenchResult], sample_every: u64, op: &str) {
+    if sample_every == 0 {
+        return;
+    }
+
+    let name_width = results.iter().map(|r| r.name.len()).max().unwrap_or(0);
+
+    println!(
+        "\nLatency of {} (ns, sampled 1 in {} calls):",
+        op, sample_every
+    );
+    print!("  {:<width$}", "", width = name_width);
+    for (label, _) in PERCENTILES {
+        print!(" {:>9}", label);
+    }
+    println!(" {:>9} {:>12}", "max", "samples");
+
+    for result in results {
+        print!("  {:<width$}", result.name, width = name_width);
+        for (_, pct) in PERCENTILES {
+            print!(" {:>9}", result.latency.percentile(pct));
+        }
+        println!(" {:>9} {:>12}", result.latency.max, result.latency.count);
+    }
+}
+
+/// Grid of configurations run by --sweep
+struct SweepGrid {
+    depths: Vec<usize>,
+    shifts: Vec<u32>,
+    tasks: Vec<usize>,
+}
+
+/// Settings shared by every configuration of a sweep
+struct SweepSettings<'a> {
+    duration: Duration,
+    round_robin: bool,
+    batch_size: usize,
+    sample_every: u64,
+    format: OutputFormat,
+    cpu_list: Option<&'a [usize]>,
+    pin: bool,
+    numa: NumaPolicy,
+    topology: &'a [Vec<usize>],
+    total_cpus: usize,
+    workload: Option<&'a Workload>,
+}
+
+/// One configuration of a sweep and its result
+struct SweepPoint {
+    config: RunConfig,
+    result: BenchResult,
+}
+
+/// Whether `shift` is worth running for a bitmap of `depth` bits
+///
+/// Once a single word covers the whole bitmap, larger shifts only repeat the
+/// same layout. Words must also be wide enough for one batch.
+fn sweep_shift_useful(shift: u32, depth: usize, batch_size: usize) -> bool {
+    let bits_per_word = 1usize << shift;
+    bits_per_word >= batch_size && (shift == 0 || (bits_per_word >> 1) < depth)
+}
+
+/// Benchmark Sbitmap for every depth, task count and shift in the grid
+///
+/// Each configuration is a separate benchmark_internal() run with its own
+/// bitmap. In CSV mode rows are printed as soon as each run finishes.
+fn run_sweep(grid: &SweepGrid, settings: &SweepSettings) -> Vec<SweepPoint> {
+    let mut points = Vec::new();
+
+    for &depth in &grid.depths {
+        for &tasks in &grid.tasks {
+            let placement = Placement::plan(
+                tasks,
+                settings.cpu_list,
+                settings.pin,
+                settings.numa,
+                settings.topology,
+            );
+            let description = placement.description.clone();
+            let params = BenchParams {
+                duration: settings.duration,
+                depth,
+                num_tasks: tasks,
+                sample_every: settings.sample_every,
+                placement,
+                verbose: false,
+            };
+
+            for &shift in &grid.shifts {
+                let max_batch = settings
+                    .workload
+                    .map_or(settings.batch_size, |w| w.max_batch());
+                if !sweep_shift_useful(shift, depth, max_batch) {
+                    continue;
+                }
+
+                let sbitmap = Arc::new(Sbitmap::new(depth, Some(shift), settings.round_robin));
+                let result = sbitmap_benchmark(
+                    "Sbitmap",
+                    sbitmap,
+                    &params,
+                    settings.batch_size,
+                    settings.workload,
+                );
+
+                let config = RunConfig {
+                    depth,
+                    shift: Some(shift),
+                    bits_per_word: 1usize << shift,
+                    tasks,
+                    batch: settings.batch_size,
+                    round_robin: settings.round_robin,
+                    duration_secs: settings.duration.as_secs(),
+                    sample_every: settings.sample_every,
+                    cpus: settings.total_cpus,
+                    numa_nodes: settings.topology.len(),
+                    placement: description.clone(),
+                    workload: settings.workload.cloned(),
+                };
+
+                match settings.format {
+                    OutputFormat::Text => println!(
+                        "  depth {:>6}, tasks {:>3}, shift {:>2}: {:>10.3} Mops/sec",
+                        depth,
+                        tasks,
+                        shift,
+                        result.ops_per_sec / 1_000_000.0
+                    ),
+                    OutputFormat::Csv => print_csv(
+                        &config,
+                        std::slice::from_ref(&result),
+                        None,
+                        points.is_empty(),
+                    ),
+                    OutputFormat::Json => {}
+                }
+
+                points.push(SweepPoint { config, result });
+            }
+        }
+    }
+
+    points
+}
+
+/// Print sweep throughput with the best shift for each depth and task count
+///
+/// "auto" is the shift Sbitmap::new() picks when none is given, and "gain"
+/// is how much faster the best shift was than the automatic one.
+fn print_sweep_table(grid: &SweepGrid, points: &[SweepPoint]) {
+    println!("\nThroughput by shift (Mops/sec):");
+    print!("  {:>8} {:>6}", "depth", "tasks");
+    for shift in &grid.shifts {
+        print!(" {:>9}", format!("shift {}", shift));
+    }
+    println!(" {:>16} {:>6} {:>8}", "best", "auto", "gain");
+
+    for &depth in &grid.depths {
+        for &tasks in &grid.tasks {
+            let row: Vec<&SweepPoint> = points
+                .iter()
+                .filter(|p| p.config.depth == depth && p.config.tasks == tasks)
+                .collect();
+            let Some(best) = row
+                .iter()
+                .max_by(|a, b| a.result.ops_per_sec.total_cmp(&b.result.ops_per_sec))
+            else {
+                continue;
+            };
+
+            print!("  {:>8} {:>6}", depth, tasks);
+            for &shift in &grid.shifts {
+                match row.iter().find(|p| p.config.shift == Some(shift)) {
+                    Some(p) => print!(" {:>9.3}", p.result.ops_per_sec / 1_000_000.0),
+                    None => print!(" {:>9}", "-"),
+                }
+            }
+
+            let best_shift = best.config.shift.unwrap_or(0);
+            let auto_shift = Sbitmap::new(depth, None, false)
+                .bits_per_word()
+                .trailing_zeros();
+            let gain = match row.iter().find(|p| p.config.shift == Some(auto_shift)) {
+                Some(auto) if auto.result.ops_per_sec > 0.0 => format!(
+                    "{:+.1}%",
+                    (speedup(&best.result, &auto.result) - 1.0) * 100.0
+                ),
+                _ => "-".to_string(),
+            };
+            println!(
+                " {:>16} {:>6} {:>8}",
+                format!("{} ({} bits)", best_shift, best.config.bits_per_word),
+                auto_shift,
+                gain
+            );
+        }
+    }
+}
+
+/// Print every sweep configuration as a JSON array of run documents
+fn print_sweep_json(points: &[SweepPoint]) {
+    let reports: Vec<String> = points
+        .iter()
+        .map(|p| json_report(&p.config, std::slice::from_ref(&p.result), None))
+        .collect();
+    println!("[\n{}\n]", reports.join(",\n"));
 }
 
 fn main() {
@@ -358,6 +2111,21 @@ fn main() {
     let mut tasks: Option<usize> = None; // Default tasks (auto-calculate: NUM_CPUS - 1)
     let mut batch_size = 1usize; // Default batch size (1 = single bit mode)
     let mut round_robin = false; // Default round-robin mode (disabled)
+    let mut sample_every = 64u64; // Default latency sampling rate (1 in 64 calls)
+    let mut cpu_list: Option<Vec<usize>> = None; // Default: all CPUs
+    let mut pin = false; // Default: let the OS scheduler place tasks
+    let mut numa = NumaPolicy::Any; // Default: no NUMA preference
+    let mut format = OutputFormat::Text; // Default: human-readable report
+    let mut sweep = false; // Default: a single configuration
+    let mut sweep_depths: Option<Vec<usize>> = None; // Default: --depth
+    let mut sweep_shifts: Option<Vec<u32>> = None; // Default: --shift, or every shift
+    let mut sweep_tasks: Option<Vec<usize>> = None; // Default: --tasks
+    let max_shift = usize::BITS.trailing_zeros();
+    let mut hold_ns: Option<u64> = None; // Default: free right after allocating
+    let mut occupancy: Option<f64> = None; // Default: no occupancy target
+    let mut batch_mix: Option<Vec<(usize, u32)>> = None; // Default: --batch only
+    let mut consumers = 0usize; // Default: every task frees its own bits
+    let mut baselines = Baseline::ALL.to_vec(); // Default: every baseline
 
     // Simple argument parser
     let mut i = 1;
@@ -440,10 +2208,188 @@ fn main() {
                 }
                 i += 2;
             }
+            "--sample-every" => {
+                if i + 1 >= args.len() {
+                    eprintln!("Error: --sample-every requires a value");
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }
+                sample_every = args[i + 1].parse::<u64>().unwrap_or_else(|_| {
+                    eprintln!("Error: Invalid sample-every value '{}'", args[i + 1]);
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                });
+                i += 2;
+            }
             "--round-robin" => {
                 round_robin = true;
                 i += 1;
             }
+            "--cpus" => {
+                if i + 1 >= args.len() {
+                    eprintln!("Error: --cpus requires a value");
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }
+                let cpus = parse_cpu_list(&args[i + 1]).unwrap_or_else(|e| {
+                    eprintln!("Error: Invalid cpus value '{}': {}", args[i + 1], e);
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                });
+                cpu_list = Some(cpus);
+                i += 2;
+            }
+            "--pin" => {
+                pin = true;
+                i += 1;
+            }
+            "--numa-spread" | "--numa-local" => {
+                let policy = if args[i] == "--numa-spread" {
+                    NumaPolicy::Spread
+                } else {
+                    NumaPolicy::Local
+                };
+                if numa != NumaPolicy::Any && numa != policy {
+                    eprintln!("Error: --numa-spread and --numa-local are mutually exclusive");
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }
+                numa = policy;
+                i += 1;
+            }
+            "--format" => {
+                if i + 1 >= args.len() {
+                    eprintln!("Error: --format requires a value");
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }
+                format = OutputFormat::parse(&args[i + 1]).unwrap_or_else(|| {
+                    eprintln!("Error: Invalid format value '{}'", args[i + 1]);
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                });
+                i += 2;
+            }
+            "--hold" => {
+                if i + 1 >= args.len() {
+                    eprintln!("Error: --hold requires a value");
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }
+                hold_ns = Some(args[i + 1].parse::<u64>().unwrap_or_else(|_| {
+                    eprintln!("Error: Invalid hold value '{}'", args[i + 1]);
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }));
+                i += 2;
+            }
+            "--occupancy" => {
+                if i + 1 >= args.len() {
+                    eprintln!("Error: --occupancy requires a value");
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }
+                let pct = args[i + 1].parse::<f64>().unwrap_or(f64::NAN);
+                if !(pct > 0.0 && pct <= 100.0) {
+                    eprintln!("Error: Invalid occupancy value '{}'", args[i + 1]);
+                    eprintln!("Occupancy is a percentage in (0, 100]");
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }
+                occupancy = Some(pct);
+                i += 2;
+            }
+            "--batch-mix" => {
+                if i + 1 >= args.len() {
+                    eprintln!("Error: --batch-mix requires a value");
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }
+                let mix = parse_batch_mix(&args[i + 1]).unwrap_or_else(|e| {
+                    eprintln!("Error: Invalid batch-mix value '{}': {}", args[i + 1], e);
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                });
+                batch_mix = Some(mix);
+                i += 2;
+            }
+            "--consumers" => {
+                if i + 1 >= args.len() {
+                    eprintln!("Error: --consumers requires a value");
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }
+                consumers = args[i + 1].parse::<usize>().unwrap_or_else(|_| {
+                    eprintln!("Error: Invalid consumers value '{}'", args[i + 1]);
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                });
+                i += 2;
+            }
+            "--baselines" => {
+                if i + 1 >= args.len() {
+                    eprintln!("Error: --baselines requires a value");
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }
+                baselines = if args[i + 1] == "none" {
+                    Vec::new()
+                } else {
+                    args[i + 1]
+                        .split(',')
+                        .map(|name| {
+                            Baseline::parse(name.trim()).unwrap_or_else(|| {
+                                eprintln!("Error: Unknown baseline '{}'", name);
+                                print_usage(&args[0]);
+                                std::process::exit(1);
+                            })
+                        })
+                        .collect()
+                };
+                i += 2;
+            }
+            "--sweep" => {
+                sweep = true;
+                i += 1;
+            }
+            "--sweep-depths" | "--sweep-shifts" | "--sweep-tasks" => {
+                let option = args[i].as_str();
+                if i + 1 >= args.len() {
+                    eprintln!("Error: {} requires a value", option);
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                }
+                let what = option.trim_start_matches("--sweep-").trim_end_matches('s');
+                let values = parse_list(&args[i + 1], what).unwrap_or_else(|e| {
+                    eprintln!("Error: Invalid {} value '{}': {}", option, args[i + 1], e);
+                    print_usage(&args[0]);
+                    std::process::exit(1);
+                });
+                match option {
+                    "--sweep-shifts" => {
+                        if let Some(&bad) = values.iter().find(|&&s| s > max_shift as usize) {
+                            eprintln!("Error: shift {} exceeds {}", bad, max_shift);
+                            print_usage(&args[0]);
+                            std::process::exit(1);
+                        }
+                        sweep_shifts = Some(values.iter().map(|&s| s as u32).collect());
+                    }
+                    _ => {
+                        if values.contains(&0) {
+                            eprintln!("Error: {} values must be at least 1", option);
+                            print_usage(&args[0]);
+                            std::process::exit(1);
+                        }
+                        if option == "--sweep-depths" {
+                            sweep_depths = Some(values);
+                        } else {
+                            sweep_tasks = Some(values);
+                        }
+                    }
+                }
+                sweep = true;
+                i += 2;
+            }
             "--help" | "-h" => {
                 print_usage(&args[0]);
                 std::process::exit(0);
@@ -465,8 +2411,9 @@ fn main() {
         .map(|n| n.get())
         .unwrap_or(1);
 
-    // Detect number of NUMA nodes
-    let numa_nodes = detect_numa_nodes();
+    // Detect NUMA topology
+    let topology = detect_numa_topology(total_cpus);
+    let numa_nodes = topology.len();
 
     // Determine number of tasks to run
     let num_cpus = match tasks {
@@ -481,59 +2428,251 @@ fn main() {
         }
     };
 
-    println!("╔═══════════════════════════════════════════════════════════╗");
-    println!("║  Sbitmap vs Simple Lockless Bitmap Benchmark Comparison   ║");
-    println!("╚═══════════════════════════════════════════════════════════╝");
-    println!();
-    println!(
-        "System: {} CPUs detected, {} NUMA nodes, using {} tasks for benchmark",
-        total_cpus, numa_nodes, num_cpus
-    );
-    println!("Bitmap depth: {} bits", depth);
+    if batch_mix.is_some() && batch_size > 1 {
+        eprintln!("Error: --batch and --batch-mix are mutually exclusive");
+        print_usage(&args[0]);
+        std::process::exit(1);
+    }
+
+    // Any workload option replaces the back-to-back get/put loop
+    let workload =
+        if hold_ns.is_some() || occupancy.is_some() || batch_mix.is_some() || consumers > 0 {
+            Some(Workload {
+                hold: Duration::from_nanos(hold_ns.unwrap_or(0)),
+                occupancy,
+                batch_mix: batch_mix.unwrap_or_else(|| vec![(batch_size, 1)]),
+                consumers,
+            })
+        } else {
+            None
+        };
+
+    if consumers > 0 {
+        let min_tasks = sweep_tasks
+            .as_ref()
+            .map_or(num_cpus, |t| *t.iter().min().unwrap());
+        if consumers >= min_tasks {
+            eprintln!(
+                "Error: --consumers ({}) must be less than the number of tasks ({})",
+                consumers, min_tasks
+            );
+            print_usage(&args[0]);
+            std::process::exit(1);
+        }
+    }
+
+    if sweep {
+        let grid = SweepGrid {
+            depths: sweep_depths.unwrap_or_else(|| vec![depth]),
+            shifts: sweep_shifts
+                .unwrap_or_else(|| shift.map_or_else(|| (0..=max_shift).collect(), |s| vec![s])),
+            tasks: sweep_tasks.unwrap_or_else(|| vec![num_cpus]),
+        };
+        let settings = SweepSettings {
+            duration,
+            round_robin,
+            batch_size,
+            sample_every,
+            format,
+            cpu_list: cpu_list.as_deref(),
+            pin,
+            numa,
+            topology: &topology,
+            total_cpus,
+            workload: workload.as_ref(),
+        };
+
+        if format == OutputFormat::Text {
+            println!("╔═══════════════════════════════════════════════════════════╗");
+            println!("║  Sbitmap Parameter Sweep                                  ║");
+            println!("╚═══════════════════════════════════════════════════════════╝");
+            println!();
+            println!(
+                "System: {} CPUs detected, {} NUMA nodes",
+                total_cpus, numa_nodes
+            );
+            println!(
+                "Sweep: {} depth(s) x {} shift(s) x {} task count(s), {} seconds per run",
+                grid.depths.len(),
+                grid.shifts.len(),
+                grid.tasks.len(),
+                duration_secs
+            );
+            println!(
+                "Round-robin: {}, batch size: {}",
+                if round_robin { "enabled" } else { "disabled" },
+                batch_size
+            );
+            if let Some(workload) = &workload {
+                println!("Workload: {}", workload.describe());
+            }
+            println!();
+        }
+
+        let points = run_sweep(&grid, &settings);
+        if points.is_empty() {
+            eprintln!("Error: no shift in the sweep has words wide enough for the batch size");
+            std::process::exit(1);
+        }
+        match format {
+            OutputFormat::Text => print_sweep_table(&grid, &points),
+            OutputFormat::Json => print_sweep_json(&points),
+            OutputFormat::Csv => {}
+        }
+        return;
+    }
 
     // Create sbitmap to get actual configuration
     let sbitmap = Arc::new(Sbitmap::new(depth, shift, round_robin));
     let bits_per_word = sbitmap.bits_per_word();
 
-    if let Some(s) = shift {
-        println!("Shift: {} (bits per word: {})", s, bits_per_word);
-    } else {
-        println!("Shift: auto-calculated (bits per word: {})", bits_per_word);
+    let placement = Placement::plan(num_cpus, cpu_list.as_deref(), pin, numa, &topology);
+
+    if format == OutputFormat::Text {
+        println!("╔═══════════════════════════════════════════════════════════╗");
+        println!("║  Sbitmap vs Simple Lockless Bitmap Benchmark Comparison   ║");
+        println!("╚═══════════════════════════════════════════════════════════╝");
+        println!();
+        println!(
+            "System: {} CPUs detected, {} NUMA nodes, using {} tasks for benchmark",
+            total_cpus, numa_nodes, num_cpus
+        );
+        println!("Bitmap depth: {} bits", depth);
+
+        if let Some(s) = shift {
+            println!("Shift: {} (bits per word: {})", s, bits_per_word);
+        } else {
+            println!("Shift: auto-calculated (bits per word: {})", bits_per_word);
+        }
+        println!(
+            "Round-robin: {}",
+            if round_robin { "enabled" } else { "disabled" }
+        );
+        println!(
+            "Batch size: {} bit{}",
+            batch_size,
+            if batch_size == 1 { "" } else { "s" }
+        );
+        println!("Duration: {} seconds", duration_secs);
+        if let Some(workload) = &workload {
+            println!("Workload: {}", workload.describe());
+        }
+
+        placement.print(&topology);
+        println!();
     }
-    println!(
-        "Round-robin: {}",
-        if round_robin { "enabled" } else { "disabled" }
-    );
-    println!(
-        "Batch size: {} bit{}",
-        batch_size,
-        if batch_size == 1 { "" } else { "s" }
-    );
-    println!("Duration: {} seconds", duration_secs);
-    println!();
 
-    if batch_size > 1 {
+    let config = RunConfig {
+        depth,
+        shift,
+        bits_per_word,
+        tasks: num_cpus,
+        batch: batch_size,
+        round_robin,
+        duration_secs,
+        sample_every,
+        cpus: total_cpus,
+        numa_nodes,
+        placement: placement.description.clone(),
+        workload: workload.clone(),
+    };
+
+    let params = BenchParams {
+        duration,
+        depth,
+        num_tasks: num_cpus,
+        sample_every,
+        placement,
+        verbose: format == OutputFormat::Text,
+    };
+
+    let max_batch = workload.as_ref().map_or(batch_size, |w| w.max_batch());
+    if max_batch > 1 {
         // Batch mode: only benchmark Sbitmap with get_batch/put_batch
-        if batch_size > bits_per_word {
+        if max_batch > bits_per_word {
             eprintln!(
                 "Error: batch size ({}) exceeds bits_per_word ({})",
-                batch_size, bits_per_word
+                max_batch, bits_per_word
             );
             eprintln!("Batch operations require nr_bits <= bits_per_word()");
             std::process::exit(1);
         }
-        batch_benchmark("Sbitmap", sbitmap, duration, depth, num_cpus, batch_size);
+        let result = sbitmap_benchmark("Sbitmap", sbitmap, &params, batch_size, workload.as_ref());
+        match format {
+            OutputFormat::Text => print_latency_table(&[result], sample_every, "get_batch()"),
+            OutputFormat::Json => print_json(&config, &[result], None),
+            OutputFormat::Csv => print_csv(&config, &[result], None, true),
+        }
     } else {
-        // Single bit mode: benchmark both Sbitmap and SimpleBitmap
+        // Single bit mode: benchmark Sbitmap and each selected baseline
         // Benchmark 1: Sbitmap (cache-line optimized with per-task hints)
-        benchmark("Sbitmap (Optimized)", sbitmap, duration, depth, num_cpus);
+        let mut results = vec![sbitmap_benchmark(
+            "Sbitmap (Optimized)",
+            sbitmap,
+            &params,
+            batch_size,
+            workload.as_ref(),
+        )];
+
+        // Then the baselines, each built fresh with the same depth
+        for baseline in &baselines {
+            results.push(baseline.run(&params, workload.as_ref()));
+        }
 
-        // Benchmark 2: SimpleBitmap (no cache-line optimization, no hints)
-        let simple = Arc::new(SimpleBitmap::new(depth));
-        benchmark("SimpleBitmap (Baseline)", simple, duration, depth, num_cpus);
+        // Compare against SimpleBitmap, or the first baseline without it
+        let reference = baselines
+            .iter()
+            .position(|&b| b == Baseline::Simple)
+            .or(if baselines.is_empty() { None } else { Some(0) })
+            .map(|i| i + 1);
+        match format {
+            OutputFormat::Text => {
+                print_throughput_table(&results, reference);
+                print_latency_table(&results, sample_every, "get()");
+            }
+            OutputFormat::Json => print_json(&config, &results, reference),
+            OutputFormat::Csv => print_csv(&config, &results, reference, true),
+        }
+    }
+
+    if format != OutputFormat::Text {
+        return;
     }
 
-    if batch_size == 1 {
+    if let Some(workload) = &workload {
+        println!("\n╔═══════════════════════════════════════════════════════════╗");
+        println!("║  Workload Summary                                         ║");
+        println!("╚═══════════════════════════════════════════════════════════╝");
+        println!(
+            "
+Tasks: {} concurrent tasks ({} allocating, {} consumer)
+Workload: {}
+Allocations in flight: {} per {}
+
+Each op is one allocation, freed {} once held for the hold time.
+Failed gets are allocations that found no free bits.
+",
+            num_cpus,
+            num_cpus - workload.consumers,
+            workload.consumers,
+            workload.describe(),
+            if workload.consumers > 0 {
+                workload.in_flight(depth, workload.consumers)
+            } else {
+                workload.in_flight(depth, num_cpus)
+            },
+            if workload.consumers > 0 {
+                "consumer queue"
+            } else {
+                "task"
+            },
+            if workload.consumers > 0 {
+                "by a consumer task"
+            } else {
+                "by the task that allocated it"
+            }
+        );
+    } else if batch_size == 1 {
         println!("\n╔═══════════════════════════════════════════════════════════╗");
         println!("║  Summary                                                  ║");
         println!("╚═══════════════════════════════════════════════════════════╝");
@@ -551,6 +2690,11 @@ SimpleBitmap characteristics:
   ✗ No allocation hints (always starts from bit 0)
   ✗ Linear scan through all words
 
+Other baselines (--baselines):
+  - Mutex<Vec<bool>>: one lock around the whole map, scan from bit 0
+  - Lock-free queue: MPMC queue of free tag ids, one CAS per get/put
+  - Per-task cache: private caches refilled from a locked global pool
+
 Expected: Sbitmap should show higher ops/sec due to:
   - Reduced false sharing between CPUs
   - Better cache locality with caller-provided hints
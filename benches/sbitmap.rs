@@ -0,0 +1,339 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Criterion microbenchmarks for sbitmap operations
+//
+// Every group except `scaling` is single-threaded. Benchmarks are
+// parameterized by depth and shift so that results can be compared across
+// commits with criterion baselines:
+//
+//   cargo bench --bench sbitmap -- --save-baseline before
+//   cargo bench --bench sbitmap -- --baseline before
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use sbitmap::{HierSbitmap, Sbitmap, SplitMix64, TagAllocator, TagCache};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bitmap depths covered by the depth x shift matrix
+const DEPTHS: [usize; 3] = [64, 1024, 16384];
+
+/// Shifts covered by the matrix; None is the auto-calculated default
+const SHIFTS: [Option<u32>; 3] = [None, Some(4), Some(usize::BITS.trailing_zeros())];
+
+/// Batch sizes for get_batch/put_batch
+const BATCH_SIZES: [usize; 5] = [2, 4, 8, 16, 32];
+
+fn shift_label(shift: Option<u32>) -> String {
+    match shift {
+        Some(s) => s.to_string(),
+        None => "auto".to_string(),
+    }
+}
+
+/// Allocate every bit, then free all bits where `keep(bit)` is false
+///
+/// The pattern is deterministic so runs on different commits measure the
+/// same bitmap state.
+fn prefill<A: TagAllocator>(sb: &A, keep: impl Fn(usize) -> bool) {
+    let mut hint = 0;
+    let mut all = Vec::with_capacity(sb.depth());
+    while let Some(bit) = sb.get(&mut hint) {
+        all.push(bit);
+    }
+    for bit in all {
+        if !keep(bit) {
+            sb.put(bit, &mut hint);
+        }
+    }
+}
+
+fn bench_get_put(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_put");
+    group.throughput(Throughput::Elements(1));
+
+    for depth in DEPTHS {
+        for shift in SHIFTS {
+            // Empty map: best-case latency of one get() + put() pair
+            let sb = Sbitmap::new(depth, shift, false);
+            let id = format!("empty/d{}/s{}", depth, shift_label(shift));
+            group.bench_function(BenchmarkId::from_parameter(id), |b| {
+                let mut hint = 0;
+                b.iter(|| {
+                    let bit = sb.get(&mut hint).unwrap();
+                    sb.put(black_box(bit), &mut hint);
+                })
+            });
+
+            // 7/8 occupied: the search has to skip allocated bits
+            let sb = Sbitmap::new(depth, shift, false);
+            prefill(&sb, |bit| bit % 8 != 0);
+            let id = format!("busy/d{}/s{}", depth, shift_label(shift));
+            group.bench_function(BenchmarkId::from_parameter(id), |b| {
+                let mut hint = 0;
+                b.iter(|| {
+                    let bit = sb.get(&mut hint).unwrap();
+                    sb.put(black_box(bit), &mut hint);
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+/// One `get()` + `put()` pair against the two atomic RMWs it is built on
+///
+/// The difference is the cost of the search, the hint and the freeze
+/// checks, so extra work on the hot path shows up here first.
+fn bench_hot_path(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hot_path");
+    group.throughput(Throughput::Elements(1));
+
+    let word = AtomicUsize::new(0);
+    group.bench_function("atomic", |b| {
+        b.iter(|| {
+            let old = word.fetch_or(1, Ordering::SeqCst);
+            word.fetch_and(!black_box(1 | old), Ordering::SeqCst);
+        })
+    });
+
+    let sb = Sbitmap::new(usize::BITS as usize, None, false);
+    group.bench_function("sbitmap", |b| {
+        let mut hint = 0;
+        b.iter(|| {
+            let bit = sb.get(&mut hint).unwrap();
+            sb.put(black_box(bit), &mut hint);
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_get_random(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_random");
+    group.throughput(Throughput::Elements(1));
+
+    // Same maps as get_put with the auto shift, to compare against it
+    for depth in DEPTHS {
+        for (state, keep) in [("empty", 0), ("busy", 8)] {
+            let sb = Sbitmap::new(depth, None, false);
+            if keep > 0 {
+                prefill(&sb, |bit| bit % keep != 0);
+            }
+            let id = format!("{}/d{}", state, depth);
+            group.bench_function(BenchmarkId::from_parameter(id), |b| {
+                let mut rng = SplitMix64::new(1);
+                let mut hint = 0;
+                b.iter(|| {
+                    let bit = sb.get_random(&mut rng, &mut hint).unwrap();
+                    sb.put(black_box(bit), &mut hint);
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_get_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_batch");
+
+    for depth in DEPTHS {
+        let sb = Sbitmap::new(depth, Some(usize::BITS.trailing_zeros()), false);
+        for nr_bits in BATCH_SIZES {
+            if nr_bits > sb.bits_per_word() {
+                continue;
+            }
+            group.throughput(Throughput::Elements(nr_bits as u64));
+            let id = format!("n{}/d{}", nr_bits, depth);
+            group.bench_function(BenchmarkId::from_parameter(id), |b| {
+                let mut hint = 0;
+                b.iter(|| {
+                    let start = sb.get_batch(nr_bits, &mut hint).unwrap();
+                    sb.put_batch(black_box(start), nr_bits, &mut hint);
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_get_batch_fragmented(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_batch_fragmented");
+
+    // Every word has short free gaps and one long run at its top end, so
+    // the search has to skip most of each word before it finds a fit
+    let bits_per_word = usize::BITS as usize;
+    for depth in DEPTHS {
+        let sb = Sbitmap::new(depth, Some(usize::BITS.trailing_zeros()), false);
+        for nr_bits in [8, 16, 32] {
+            let tail = bits_per_word - nr_bits;
+            prefill(&sb, |bit| {
+                let offset = bit % bits_per_word;
+                offset < tail && offset % 4 != 0
+            });
+            group.throughput(Throughput::Elements(nr_bits as u64));
+            let id = format!("n{}/d{}", nr_bits, depth);
+            group.bench_function(BenchmarkId::from_parameter(id), |b| {
+                let mut hint = 0;
+                b.iter(|| {
+                    let start = sb.get_batch(nr_bits, &mut hint).unwrap();
+                    sb.put_batch(black_box(start), nr_bits, &mut hint);
+                })
+            });
+
+            // Free everything for the next batch size's layout
+            let mut hint = 0;
+            for bit in 0..depth {
+                if sb.test_bit(bit) {
+                    sb.put(bit, &mut hint);
+                }
+            }
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_weight(c: &mut Criterion) {
+    let mut group = c.benchmark_group("weight");
+
+    for depth in DEPTHS {
+        for shift in SHIFTS {
+            let sb = Sbitmap::new(depth, shift, false);
+            prefill(&sb, |bit| bit % 2 == 0);
+            group.throughput(Throughput::Elements(depth as u64));
+            let id = format!("d{}/s{}", depth, shift_label(shift));
+            group.bench_function(BenchmarkId::from_parameter(id), |b| {
+                b.iter(|| black_box(sb.weight()))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_iterate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("iterate");
+
+    for depth in DEPTHS {
+        for shift in SHIFTS {
+            let sb = Sbitmap::new(depth, shift, false);
+            prefill(&sb, |bit| bit % 3 == 0);
+            group.throughput(Throughput::Elements(depth as u64));
+            let id = format!("d{}/s{}", depth, shift_label(shift));
+            group.bench_function(BenchmarkId::from_parameter(id), |b| {
+                // Visit every allocated bit through test_bit()
+                b.iter(|| (0..sb.depth()).filter(|&bit| sb.test_bit(bit)).count())
+            });
+        }
+    }
+
+    group.finish();
+}
+
+/// Depth of the large maps comparing the flat and hierarchical searches
+const LARGE_DEPTH: usize = 1 << 20;
+
+/// Name, which bits stay allocated, and whether the hint moves on
+type Layout = (&'static str, fn(usize) -> bool, bool);
+
+fn bench_large(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large");
+    group.throughput(Throughput::Elements(1));
+
+    // "sparse": one free bit in every 4096, the next get() starts one word
+    // past the last one, so a flat search loads ~64 full words per get().
+    // "tail": only the last word is free and every get() starts at bit 0,
+    // so a flat search loads every word.
+    let layouts: [Layout; 2] = [
+        ("sparse", |bit| bit % 4096 != 0, true),
+        ("tail", |bit| bit < LARGE_DEPTH - 64, false),
+    ];
+
+    for (layout, keep, advance) in layouts {
+        let sb = Sbitmap::new(LARGE_DEPTH, Some(usize::BITS.trailing_zeros()), false);
+        prefill(&sb, keep);
+        group.bench_function(BenchmarkId::new("flat", layout), |b| {
+            let mut hint = 0;
+            b.iter(|| {
+                let bit = sb.get(&mut hint).unwrap();
+                sb.put(black_box(bit), &mut hint);
+                hint = if advance { (bit + 64) % LARGE_DEPTH } else { 0 };
+            })
+        });
+
+        let sb = HierSbitmap::new(LARGE_DEPTH);
+        prefill(&sb, keep);
+        group.bench_function(BenchmarkId::new("hier", layout), |b| {
+            let mut hint = 0;
+            b.iter(|| {
+                let bit = sb.get(&mut hint).unwrap();
+                sb.put(black_box(bit), &mut hint);
+                hint = if advance { (bit + 64) % LARGE_DEPTH } else { 0 };
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Thread counts of the scaling group
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+
+/// Wall-clock time for `threads` threads to each run `iters` get()+put() pairs
+fn run_threads<A: TagAllocator + Sync>(sb: &A, threads: usize, iters: u64) -> Duration {
+    let start = Barrier::new(threads + 1);
+    // The scope joins every thread before returning the start time
+    let begin = thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                let mut hint = 0;
+                start.wait();
+                for _ in 0..iters {
+                    let bit = sb.get(&mut hint).unwrap();
+                    sb.put(black_box(bit), &mut hint);
+                }
+            });
+        }
+        start.wait();
+        Instant::now()
+    });
+    begin.elapsed()
+}
+
+fn bench_scaling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scaling");
+
+    // Every thread runs get()+put() pairs on one shared allocator; the
+    // per-CPU magazines of TagCache should keep its throughput growing
+    // with the thread count where a bare Sbitmap contends on its words
+    for threads in THREADS {
+        group.throughput(Throughput::Elements(threads as u64));
+
+        let sb = Sbitmap::new(1024, None, false);
+        group.bench_function(BenchmarkId::new("sbitmap", threads), |b| {
+            b.iter_custom(|iters| run_threads(&sb, threads, iters))
+        });
+
+        let cache = TagCache::new(Sbitmap::new(1024, None, false), 16);
+        group.bench_function(BenchmarkId::new("cache", threads), |b| {
+            b.iter_custom(|iters| run_threads(&cache, threads, iters))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default()
+        .warm_up_time(Duration::from_secs(1))
+        .measurement_time(Duration::from_secs(2));
+    targets = bench_get_put, bench_hot_path, bench_get_random, bench_get_batch, bench_get_batch_fragmented, bench_weight, bench_iterate, bench_large, bench_scaling
+}
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Drive an Sbitmap from arbitrary operation sequences and check invariants
+//
+// The checks live in the reference model shared with the property tests:
+// every allocation must return a free, in-range bit (or a single-word run
+// for batches), test_bit() and weight() must agree with the model, and an
+// allocation may only fail when the model has no room either.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/model/mod.rs"]
+mod model;
+
+use model::{run_case, Op, POLICIES};
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    depth: u16,
+    shift: Option<u8>,
+    policy: u8,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| {
+    let max_shift = usize::BITS.trailing_zeros();
+    let depth = input.depth as usize % 1024;
+    let shift = input.shift.map(|s| s as u32 % (max_shift + 1));
+    let policy = POLICIES[input.policy as usize % POLICIES.len()];
+
+    run_case(depth, shift, policy, input.ops);
+});
@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Fuzz the word-level search helpers against naive bit-by-bit references
+//
+// Covers the edge cases hint == depth, depth == BITS_PER_WORD and
+// nr_bits == BITS_PER_WORD.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use sbitmap::fuzz::{find_next_zero_batch, find_next_zero_bit, make_mask, BITS_PER_WORD};
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    word: usize,
+    depth: u8,
+    hint: u8,
+    nr_bits: u8,
+}
+
+fn bit_is_set(word: usize, bit: usize) -> bool {
+    (word >> bit) & 1 != 0
+}
+
+fuzz_target!(|input: Input| {
+    let word = input.word;
+    let depth = input.depth as usize % (BITS_PER_WORD + 1);
+    let hint = input.hint as usize % (depth + 1);
+    let nr_bits = 1 + input.nr_bits as usize % BITS_PER_WORD;
+
+    // make_mask: exactly nr_bits low bits set
+    let mask = make_mask(nr_bits);
+    assert_eq!(mask.count_ones() as usize, nr_bits);
+    assert_eq!(mask.trailing_ones() as usize, nr_bits);
+
+    // find_next_zero_bit: first clear bit in [hint, depth)
+    let expected = (hint..depth).find(|&bit| !bit_is_set(word, bit));
+    assert_eq!(
+        find_next_zero_bit(word, depth, hint),
+        expected,
+        "find_next_zero_bit({word:#x}, {depth}, {hint})"
+    );
+
+    // find_next_zero_batch: first run of nr_bits clear bits starting in
+    // [hint, depth - nr_bits]
+    let expected = (hint..depth)
+        .filter(|&start| start + nr_bits <= depth)
+        .find(|&start| (start..start + nr_bits).all(|bit| !bit_is_set(word, bit)));
+    assert_eq!(
+        find_next_zero_batch(word, depth, hint, nr_bits),
+        expected,
+        "find_next_zero_batch({word:#x}, {depth}, {hint}, {nr_bits})"
+    );
+});
@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Common interface for tag allocators
+//
+// Code that hands out tags can be written against `TagAllocator` and then
+// run with an `Sbitmap`, another allocator variant, or a mock in tests.
+
+use crate::{BitmapWord, Sbitmap};
+use std::sync::Arc;
+
+/// A concurrent allocator of tags in `0..depth()`
+///
+/// The methods mirror `Sbitmap::get()` and `Sbitmap::put()`, including the
+/// caller-owned hint. Allocators that do not use a hint may ignore it.
+pub trait TagAllocator {
+    /// Allocate a free tag
+    ///
+    /// # Arguments
+    /// * `hint` - Mutable reference to caller's allocation hint
+    ///
+    /// # Returns
+    /// * `Some(tag)` - Successfully allocated tag
+    /// * `None` - No free tags available
+    fn get(&self, hint: &mut usize) -> Option<usize>;
+
+    /// Free a tag previously returned by get()
+    ///
+    /// # Arguments
+    /// * `tag` - The tag to free
+    /// * `hint` - Mutable reference to caller's allocation hint
+    fn put(&self, tag: usize, hint: &mut usize);
+
+    /// Get the number of tags managed by the allocator
+    fn depth(&self) -> usize;
+}
+
+impl<W: BitmapWord> TagAllocator for Sbitmap<W> {
+    #[inline]
+    fn get(&self, hint: &mut usize) -> Option<usize> {
+        Sbitmap::get(self, hint)
+    }
+
+    #[inline]
+    fn put(&self, tag: usize, hint: &mut usize) {
+        Sbitmap::put(self, tag, hint)
+    }
+
+    #[inline]
+    fn depth(&self) -> usize {
+        Sbitmap::depth(self)
+    }
+}
+
+impl<T: TagAllocator + ?Sized> TagAllocator for &T {
+    #[inline]
+    fn get(&self, hint: &mut usize) -> Option<usize> {
+        (**self).get(hint)
+    }
+
+    #[inline]
+    fn put(&self, tag: usize, hint: &mut usize) {
+        (**self).put(tag, hint)
+    }
+
+    #[inline]
+    fn depth(&self) -> usize {
+        (**self).depth()
+    }
+}
+
+impl<T: TagAllocator + ?Sized> TagAllocator for Box<T> {
+    #[inline]
+    fn get(&self, hint: &mut usize) -> Option<usize> {
+        (**self).get(hint)
+    }
+
+    #[inline]
+    fn put(&self, tag: usize, hint: &mut usize) {
+        (**self).put(tag, hint)
+    }
+
+    #[inline]
+    fn depth(&self) -> usize {
+        (**self).depth()
+    }
+}
+
+impl<T: TagAllocator + ?Sized> TagAllocator for Arc<T> {
+    #[inline]
+    fn get(&self, hint: &mut usize) -> Option<usize> {
+        (**self).get(hint)
+    }
+
+    #[inline]
+    fn put(&self, tag: usize, hint: &mut usize) {
+        (**self).put(tag, hint)
+    }
+
+    #[inline]
+    fn depth(&self) -> usize {
+        (**self).depth()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Allocator handing out a fixed list of tags and recording every put
+    struct MockAllocator {
+        free: Mutex<Vec<usize>>,
+        puts: Mutex<Vec<usize>>,
+    }
+
+    impl MockAllocator {
+        fn new(tags: &[usize]) -> Self {
+            Self {
+                free: Mutex::new(tags.iter().rev().copied().collect()),
+                puts: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl TagAllocator for MockAllocator {
+        fn get(&self, _hint: &mut usize) -> Option<usize> {
+            self.free.lock().unwrap().pop()
+        }
+
+        fn put(&self, tag: usize, _hint: &mut usize) {
+            self.puts.lock().unwrap().push(tag);
+        }
+
+        fn depth(&self) -> usize {
+            16
+        }
+    }
+
+    /// Generic caller: run `f` with a tag, then give the tag back
+    fn with_tag<A: TagAllocator, R>(alloc: &A, f: impl FnOnce(usize) -> R) -> Option<R> {
+        let mut hint = 0;
+        let tag = alloc.get(&mut hint)?;
+        let result = f(tag);
+        alloc.put(tag, &mut hint);
+        Some(result)
+    }
+
+    /// Generic caller: allocate until the allocator runs out
+    fn drain<A: TagAllocator>(alloc: &A) -> Vec<usize> {
+        let mut hint = 0;
+        std::iter::from_fn(|| alloc.get(&mut hint)).collect()
+    }
+
+    #[test]
+    fn test_sbitmap_through_trait() {
+        let sb = Sbitmap::new(32, Some(3), false);
+
+        let mut tags = drain(&sb);
+        assert_eq!(tags.len(), TagAllocator::depth(&sb));
+        tags.sort_unstable();
+        assert_eq!(tags, (0..32).collect::<Vec<_>>());
+
+        let mut hint = 0;
+        TagAllocator::put(&sb, 5, &mut hint);
+        assert!(!sb.test_bit(5));
+        assert_eq!(with_tag(&sb, |tag| tag), Some(5));
+        assert!(!sb.test_bit(5));
+    }
+
+    #[test]
+    fn test_mock_allocator() {
+        let mock = MockAllocator::new(&[7, 3]);
+
+        assert_eq!(with_tag(&mock, |tag| tag * 2), Some(14));
+        assert_eq!(with_tag(&mock, |tag| tag * 2), Some(6));
+        assert_eq!(with_tag(&mock, |tag| tag * 2), None);
+        assert_eq!(*mock.puts.lock().unwrap(), vec![7, 3]);
+    }
+
+    #[test]
+    fn test_trait_objects_and_wrappers() {
+        let allocators: Vec<Box<dyn TagAllocator>> = vec![
+            Box::new(Sbitmap::new(8, None, false)),
+            Box::new(MockAllocator::new(&[0, 1, 2])),
+        ];
+        let counts: Vec<usize> = allocators.iter().map(|a| drain(a).len()).collect();
+        assert_eq!(counts, vec![8, 3]);
+        assert_eq!(allocators[1].depth(), 16);
+
+        let shared = Arc::new(Sbitmap::new(4, None, false));
+        assert_eq!(drain(&Arc::clone(&shared)).len(), 4);
+        assert_eq!(shared.weight(), 4);
+    }
+}
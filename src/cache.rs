@@ -0,0 +1,477 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Per-CPU tag cache in front of an sbitmap
+//
+// Each CPU keeps a small magazine of tags that are allocated in the
+// underlying `Sbitmap` but not handed out yet. `get()` and `put()` normally
+// only touch the magazine of the current CPU; the shared bitmap words are
+// touched when a magazine is refilled or drained, a batch at a time.
+
+use crate::numa;
+use crate::{Sbitmap, TagAllocator};
+use std::sync::Mutex;
+
+/// Number of tags in one cache line of magazine storage
+const TAGS_PER_LINE: usize = 64 / std::mem::size_of::<usize>();
+
+/// One cache line of tag slots
+#[derive(Clone, Copy)]
+#[repr(align(64))]
+struct TagLine([usize; TAGS_PER_LINE]);
+
+/// Stack of tags in storage made of whole cache lines
+///
+/// A `Vec<usize>` of a few tags is a small heap block that the allocator
+/// packs next to other small blocks, e.g. the buffers of other magazines.
+/// Whole, aligned lines share no cache line with any other allocation.
+struct TagStack {
+    /// Tag slots, the first `len` in use
+    lines: Box<[TagLine]>,
+    /// Number of tags held
+    len: usize,
+}
+
+impl TagStack {
+    /// Create an empty stack with room for at least `capacity` tags
+    fn with_capacity(capacity: usize) -> Self {
+        let nr_lines = capacity.div_ceil(TAGS_PER_LINE);
+        Self {
+            lines: vec![TagLine([0; TAGS_PER_LINE]); nr_lines].into_boxed_slice(),
+            len: 0,
+        }
+    }
+
+    /// Tag in slot `i`
+    fn slot(&self, i: usize) -> usize {
+        self.lines[i / TAGS_PER_LINE].0[i % TAGS_PER_LINE]
+    }
+
+    /// Mutable reference to slot `i`
+    fn slot_mut(&mut self, i: usize) -> &mut usize {
+        &mut self.lines[i / TAGS_PER_LINE].0[i % TAGS_PER_LINE]
+    }
+
+    /// Number of tags held
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Push a tag; the caller keeps `len()` within the capacity
+    fn push(&mut self, tag: usize) {
+        *self.slot_mut(self.len) = tag;
+        self.len += 1;
+    }
+
+    /// Pop the most recently pushed tag
+    fn pop(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.slot(self.len))
+    }
+
+    /// Check whether `tag` is held
+    fn contains(&self, tag: usize) -> bool {
+        (0..self.len).any(|i| self.slot(i) == tag)
+    }
+
+    /// Remove the `nr` oldest tags
+    fn drain_oldest(&mut self, nr: usize) -> Vec<usize> {
+        let nr = nr.min(self.len);
+        let oldest = (0..nr).map(|i| self.slot(i)).collect();
+        for i in nr..self.len {
+            *self.slot_mut(i - nr) = self.slot(i);
+        }
+        self.len -= nr;
+        oldest
+    }
+
+    /// Remove every tag
+    fn take_all(&mut self) -> Vec<usize> {
+        self.drain_oldest(self.len)
+    }
+}
+
+/// One CPU's magazine of cached tags
+///
+/// Aligned to a cache line so that magazines of different CPUs never
+/// share one, and the tags live in lines of their own, so get() and put()
+/// only write this CPU's lines on a magazine hit.
+#[repr(align(64))]
+struct Magazine {
+    /// Tags allocated in the bitmap and not handed out, most recent last
+    tags: Mutex<TagStack>,
+}
+
+/// Sbitmap wrapper with per-CPU magazines of pre-allocated tags
+///
+/// A magazine holds up to `capacity` tags. An empty magazine is refilled
+/// with `capacity / 2` tags using `get_batch()` where possible, and a full
+/// one drains its oldest `capacity / 2` tags back with `put_batch()`.
+///
+/// Cached tags are never lost to other CPUs: when the bitmap runs out,
+/// `get()` takes tags from other magazines before failing, so `get()`
+/// returns None only if every tag is really in use. `weight()` counts
+/// tags handed out to callers, not tags sitting in magazines.
+///
+/// There is no shared counter: `weight()` and `cached()` lock every
+/// magazine in turn, so they cost O(magazines) and are meant for
+/// diagnostics, not the fast path.
+pub struct TagCache {
+    /// The underlying bitmap
+    map: Sbitmap,
+    /// One magazine per CPU
+    magazines: Box<[Magazine]>,
+    /// Maximum number of tags per magazine
+    capacity: usize,
+}
+
+impl TagCache {
+    /// Create a tag cache with one magazine per possible CPU
+    ///
+    /// # Arguments
+    /// * `map` - The bitmap to allocate tags from
+    /// * `capacity` - Maximum number of tags cached per CPU (at least 1)
+    pub fn new(map: Sbitmap, capacity: usize) -> Self {
+        let max_cpu = numa::online_nodes().into_iter().flatten().max();
+        let nr_magazines = match max_cpu {
+            Some(cpu) => cpu + 1,
+            None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+        Self::with_magazines(map, capacity, nr_magazines)
+    }
+
+    /// Create a tag cache with a fixed number of magazines
+    ///
+    /// Callers on CPU `n` use magazine `n % nr_magazines`.
+    ///
+    /// # Arguments
+    /// * `map` - The bitmap to allocate tags from
+    /// * `capacity` - Maximum number of tags cached per magazine (at least 1)
+    /// * `nr_magazines` - Number of magazines (at least 1)
+    pub fn with_magazines(map: Sbitmap, capacity: usize, nr_magazines: usize) -> Self {
+        let capacity = capacity.max(1);
+        let magazines = (0..nr_magazines.max(1))
+            .map(|_| Magazine {
+                tags: Mutex::new(TagStack::with_capacity(capacity)),
+            })
+            .collect();
+
+        Self {
+            map,
+            magazines,
+            capacity,
+        }
+    }
+
+    /// Index of the magazine for the calling thread's CPU
+    fn local_magazine(&self) -> usize {
+        numa::current_cpu().map_or(0, |cpu| cpu % self.magazines.len())
+    }
+
+    /// Allocate a tag, from the local magazine if possible
+    ///
+    /// # Arguments
+    /// * `hint` - Mutable reference to caller's allocation hint, used when
+    ///   refilling the magazine from the bitmap
+    ///
+    /// # Returns
+    /// * `Some(tag)` - Successfully allocated tag
+    /// * `None` - Every tag is handed out
+    pub fn get(&self, hint: &mut usize) -> Option<usize> {
+        self.get_on(self.local_magazine(), hint)
+    }
+
+    /// Allocate a tag using magazine `index`
+    fn get_on(&self, index: usize, hint: &mut usize) -> Option<usize> {
+        {
+            let mut tags = self.magazines[index].tags.lock().unwrap();
+            if let Some(tag) = tags.pop() {
+                return Some(tag);
+            }
+            if let Some(tag) = self.refill(&mut tags, hint) {
+                return Some(tag);
+            }
+        }
+
+        // The bitmap is exhausted, take a tag cached by another CPU. The
+        // local magazine is unlocked first so two stealers can't deadlock.
+        let nr = self.magazines.len();
+        for i in 1..nr {
+            let mut tags = self.magazines[(index + i) % nr].tags.lock().unwrap();
+            if let Some(tag) = tags.pop() {
+                return Some(tag);
+            }
+        }
+
+        None
+    }
+
+    /// Refill an empty magazine and return one tag from it
+    fn refill(&self, tags: &mut TagStack, hint: &mut usize) -> Option<usize> {
+        let want = (self.capacity / 2).max(1);
+
+        // One consecutive run within a word, if the bitmap has one
+        let run = want.min(self.map.bits_per_word());
+        if run > 1 {
+            if let Some(start) = self.map.get_batch(run, hint) {
+                for tag in (start..start + run).rev() {
+                    tags.push(tag);
+                }
+            }
+        }
+
+        // Otherwise, or for whatever is left, single bits
+        while tags.len() < want {
+            match self.map.get(hint) {
+                Some(tag) => tags.push(tag),
+                None => break,
+            }
+        }
+
+        tags.pop()
+    }
+
+    /// Free a tag into the local magazine
+    ///
+    /// Unlike `Sbitmap::put()`, freeing a tag twice is not harmless: both
+    /// copies are cached and later handed to two callers. Debug builds
+    /// catch a double free into the same magazine.
+    ///
+    /// # Arguments
+    /// * `tag` - The tag to free (must have been returned by get())
+    /// * `hint` - Mutable reference to caller's allocation hint, used when
+    ///   draining the magazine to the bitmap
+    pub fn put(&self, tag: usize, hint: &mut usize) {
+        self.put_on(self.local_magazine(), tag, hint)
+    }
+
+    /// Free a tag into magazine `index`
+    fn put_on(&self, index: usize, tag: usize, hint: &mut usize) {
+        if tag >= self.map.depth() {
+            return; // Invalid tag
+        }
+
+        let mut tags = self.magazines[index].tags.lock().unwrap();
+        debug_assert!(!tags.contains(tag), "double free of tag {tag}");
+        if tags.len() == self.capacity {
+            let oldest = tags.drain_oldest((self.capacity / 2).max(1));
+            self.release(oldest, hint);
+        }
+        tags.push(tag);
+    }
+
+    /// Free tags in the bitmap, batching runs that share a word
+    fn release(&self, mut tags: Vec<usize>, hint: &mut usize) {
+        tags.sort_unstable();
+
+        let bits_per_word = self.map.bits_per_word();
+        let mut i = 0;
+        while i < tags.len() {
+            let start = tags[i];
+            let mut len = 1;
+            while i + len < tags.len()
+                && tags[i + len] == start + len
+                && (start + len) / bits_per_word == start / bits_per_word
+            {
+                len += 1;
+            }
+            self.map.put_batch(start, len, hint);
+            i += len;
+        }
+    }
+
+    /// Return every cached tag to the bitmap
+    ///
+    /// Afterwards the bitmap's own weight equals `weight()`.
+    pub fn flush(&self) {
+        let mut hint = 0;
+        for magazine in self.magazines.iter() {
+            let tags = magazine.tags.lock().unwrap().take_all();
+            self.release(tags, &mut hint);
+        }
+    }
+
+    /// Count the tags handed out to callers
+    ///
+    /// Tags cached in magazines are not counted. Like `Sbitmap::weight()`,
+    /// this is a snapshot that may be stale under concurrent use.
+    pub fn weight(&self) -> usize {
+        self.map.weight().saturating_sub(self.cached())
+    }
+
+    /// Count the tags currently cached in magazines
+    ///
+    /// Locks each magazine in turn; O(magazines).
+    pub fn cached(&self) -> usize {
+        self.magazines
+            .iter()
+            .map(|magazine| magazine.tags.lock().unwrap().len())
+            .sum()
+    }
+
+    /// Get the total number of tags
+    pub fn depth(&self) -> usize {
+        self.map.depth()
+    }
+
+    /// Get the maximum number of tags cached per magazine
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get the underlying bitmap
+    ///
+    /// Its weight includes cached tags; call flush() first for an exact view.
+    pub fn inner(&self) -> &Sbitmap {
+        &self.map
+    }
+
+    /// Flush all magazines and return the underlying bitmap
+    pub fn into_inner(self) -> Sbitmap {
+        self.flush();
+        self.map
+    }
+}
+
+impl TagAllocator for TagCache {
+    #[inline]
+    fn get(&self, hint: &mut usize) -> Option<usize> {
+        TagCache::get(self, hint)
+    }
+
+    #[inline]
+    fn put(&self, tag: usize, hint: &mut usize) {
+        TagCache::put(self, tag, hint)
+    }
+
+    #[inline]
+    fn depth(&self) -> usize {
+        TagCache::depth(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_refill_and_drain() {
+        let cache = TagCache::with_magazines(Sbitmap::new(64, Some(4), false), 8, 2);
+        let mut hint = 0;
+
+        // First get refills half a magazine with one batch
+        let tag = cache.get_on(0, &mut hint).unwrap();
+        assert_eq!(cache.weight(), 1);
+        assert_eq!(cache.cached(), 3);
+        assert_eq!(cache.inner().weight(), 4);
+
+        // Gets from the magazine don't touch the bitmap
+        let more: Vec<usize> = (0..3)
+            .map(|_| cache.get_on(0, &mut hint).unwrap())
+            .collect();
+        assert_eq!(cache.cached(), 0);
+        assert_eq!(cache.inner().weight(), 4);
+
+        // Puts fill the magazine, then the oldest half goes back
+        let mut all = vec![tag];
+        all.extend(more);
+        all.extend((0..6).map(|_| cache.get_on(1, &mut hint).unwrap()));
+        for &t in &all {
+            cache.put_on(0, t, &mut hint);
+        }
+        assert_eq!(cache.weight(), 0);
+        // Magazine 0 drained 4 and holds 6, magazine 1 holds 2 from its refill
+        assert_eq!(cache.cached(), 8);
+        assert_eq!(cache.inner().weight(), 8);
+
+        cache.flush();
+        assert_eq!(cache.cached(), 0);
+        assert_eq!(cache.into_inner().weight(), 0);
+    }
+
+    #[test]
+    fn test_exhaustion_takes_cached_tags() {
+        let cache = TagCache::with_magazines(Sbitmap::new(16, None, false), 8, 4);
+        let mut hint = 0;
+
+        // Park tags in magazine 0
+        let parked: Vec<usize> = (0..4)
+            .map(|_| cache.get_on(0, &mut hint).unwrap())
+            .collect();
+        for t in parked {
+            cache.put_on(0, t, &mut hint);
+        }
+        assert!(cache.cached() > 0);
+
+        // Another CPU can still get every tag
+        let mut tags: Vec<usize> = std::iter::from_fn(|| cache.get_on(2, &mut hint)).collect();
+        tags.sort_unstable();
+        assert_eq!(tags, (0..16).collect::<Vec<_>>());
+        assert_eq!(cache.weight(), 16);
+        assert_eq!(cache.cached(), 0);
+        assert_eq!(cache.get_on(3, &mut hint), None);
+    }
+
+    #[test]
+    fn test_magazines_own_their_lines() {
+        let cache = TagCache::with_magazines(Sbitmap::new(256, None, false), 4, 8);
+
+        // Cache lines written on a hit: the magazine and its tag storage
+        let mut lines = Vec::new();
+        for magazine in cache.magazines.iter() {
+            let tags = magazine.tags.lock().unwrap();
+            let buffer = tags.lines.as_ptr() as usize;
+            assert_eq!(buffer % 64, 0);
+            lines.push(magazine as *const Magazine as usize / 64);
+            lines.extend((0..tags.lines.len()).map(|i| buffer / 64 + i));
+        }
+
+        let nr = lines.len();
+        lines.sort_unstable();
+        lines.dedup();
+        assert_eq!(lines.len(), nr, "magazines share a cache line");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "double free of tag")]
+    fn test_double_put() {
+        let cache = TagCache::with_magazines(Sbitmap::new(16, None, false), 8, 1);
+        let mut hint = 0;
+        let tag = cache.get_on(0, &mut hint).unwrap();
+        cache.put_on(0, tag, &mut hint);
+        cache.put_on(0, tag, &mut hint);
+    }
+
+    #[test]
+    fn test_concurrent_cache() {
+        let cache = Arc::new(TagCache::new(Sbitmap::new(128, None, false), 16));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    let mut hint = 0;
+                    for _ in 0..1000 {
+                        let held: Vec<usize> =
+                            (0..8).filter_map(|_| cache.get(&mut hint)).collect();
+                        assert_eq!(held.len(), 8);
+                        for tag in held {
+                            cache.put(tag, &mut hint);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(cache.weight(), 0);
+        cache.flush();
+        assert_eq!(cache.inner().weight(), 0);
+    }
+}
@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Freezing an sbitmap and waiting for it to drain
+//
+// Like `blk_mq_freeze_queue()`: `freeze()` makes new allocations fail and
+// `wait_idle()` sleeps until every allocated bit has been freed, woken by
+// `put()`. An allocation checks the freeze again after setting its bits
+// and backs out if it lost the race, so once `wait_idle()` has seen the
+// map idle, no bit allocated concurrently is handed out.
+//
+// The hot paths add no fence: the bit-word RMWs are SeqCst, and a SeqCst
+// RMW followed by a SeqCst load of the freeze flag (or waiter count) pairs
+// with the store and fence in `wait_idle()`. Either the waiter sees the
+// bit change, or the allocator sees the flag (the freer sees the waiter).
+// Loom models SeqCst accesses as AcqRel, which misses this, so under loom
+// the hot paths get the equivalent fence instead.
+
+#[cfg(loom)]
+use loom::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+#[cfg(loom)]
+use loom::sync::{Condvar, Mutex};
+#[cfg(not(loom))]
+use std::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+use std::sync::{Condvar, Mutex};
+
+use crate::{BitmapWord, Sbitmap};
+use std::time::{Duration, Instant};
+
+/// Freeze flag and the threads sleeping in `wait_idle()`
+pub(crate) struct FreezeState {
+    /// Whether new allocations fail
+    frozen: AtomicBool,
+    /// Number of threads in `wait_idle()`
+    waiters: AtomicUsize,
+    /// Held while checking for idle and going to sleep, so no wakeup is missed
+    lock: Mutex<()>,
+    /// Notified by frees while there are waiters
+    idle: Condvar,
+}
+
+/// Stand-in for the SeqCst bit RMWs under loom, which only models fences
+#[inline(always)]
+fn loom_fence() {
+    #[cfg(loom)]
+    fence(Ordering::SeqCst);
+}
+
+impl FreezeState {
+    /// Create an unfrozen state without waiters
+    pub(crate) fn new() -> Self {
+        Self {
+            frozen: AtomicBool::new(false),
+            waiters: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            idle: Condvar::new(),
+        }
+    }
+
+    /// Whether new allocations fail
+    #[inline]
+    pub(crate) fn is_frozen(&self) -> bool {
+        self.frozen.load(Ordering::Relaxed)
+    }
+
+    /// Wake the threads in `wait_idle()` after bits were freed
+    ///
+    /// Must follow the SeqCst RMW that freed the bits. Costs one load; the
+    /// lock is only taken when someone waits.
+    #[inline]
+    pub(crate) fn wake_waiters(&self) {
+        loom_fence();
+        if self.waiters.load(Ordering::SeqCst) != 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.idle.notify_all();
+        }
+    }
+}
+
+impl<W: BitmapWord> Sbitmap<W> {
+    /// Run an allocation unless the bitmap is frozen
+    ///
+    /// If `freeze()` raced with `search`, the `nr_bits` bits it allocated
+    /// are freed again and None is returned.
+    #[inline]
+    pub(crate) fn admit<F>(&self, nr_bits: usize, search: F) -> Option<usize>
+    where
+        F: FnOnce() -> Option<usize>,
+    {
+        if self.freeze.is_frozen() {
+            return None;
+        }
+        let bitnr = search()?;
+
+        // Ordered after the SeqCst RMW that set the bits: either the
+        // freeze is seen here, or the bits are seen by wait_idle()
+        loom_fence();
+        if !self.freeze.frozen.load(Ordering::SeqCst) {
+            return Some(bitnr);
+        }
+
+        let mut hint = 0;
+        self.put_batch(bitnr, nr_bits, &mut hint);
+        None
+    }
+
+    /// Stop new allocations
+    ///
+    /// Afterwards `get()`, `get_batch()`, `get_lowest()` and `get_random()`
+    /// return None until `unfreeze()`. Allocations already in progress
+    /// either complete before `wait_idle()` can see the bitmap idle, or
+    /// fail. Freeing bits is not affected.
+    pub fn freeze(&self) {
+        self.freeze.frozen.store(true, Ordering::SeqCst);
+        log::debug!("sbitmap::freeze: weight={}", self.weight());
+    }
+
+    /// Allow allocations again after `freeze()`
+    pub fn unfreeze(&self) {
+        self.freeze.frozen.store(false, Ordering::SeqCst);
+    }
+
+    /// Check whether the bitmap is frozen
+    pub fn is_frozen(&self) -> bool {
+        self.freeze.is_frozen()
+    }
+
+    /// Wait until no bits are allocated
+    ///
+    /// Normally called after `freeze()`; without it, new allocations may
+    /// keep the bitmap busy. The caller sleeps and is woken by `put()` and
+    /// `put_batch()`. Every free rechecks the weight while someone waits,
+    /// which costs O(words) each time.
+    ///
+    /// # Arguments
+    /// * `timeout` - Maximum time to wait; `Duration::MAX` waits forever
+    ///
+    /// # Returns
+    /// * `true` - No bits were allocated at some point before returning
+    /// * `false` - The timeout expired first
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let state = &self.freeze;
+        let deadline = Instant::now().checked_add(timeout);
+
+        state.waiters.fetch_add(1, Ordering::SeqCst);
+        // Orders the freeze and the waiter count before reading the words,
+        // against the SeqCst bit RMWs in get() and put()
+        fence(Ordering::SeqCst);
+
+        let mut guard = state.lock.lock().unwrap();
+        let idle = loop {
+            if self.weight() == 0 {
+                break true;
+            }
+            guard = match deadline {
+                None => state.idle.wait(guard).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break false;
+                    }
+                    state.idle.wait_timeout(guard, deadline - now).unwrap().0
+                }
+            };
+        };
+        drop(guard);
+
+        state.waiters.fetch_sub(1, Ordering::Relaxed);
+        idle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_freeze_blocks_allocation() {
+        let sb = Sbitmap::new(64, Some(3), false);
+        let mut hint = 0;
+        let bit = sb.get(&mut hint).unwrap();
+
+        sb.freeze();
+        assert!(sb.is_frozen());
+        assert_eq!(sb.get(&mut hint), None);
+        assert_eq!(sb.get_batch(4, &mut hint), None);
+        assert_eq!(sb.get_lowest(), None);
+        assert!(!sb.wait_idle(Duration::from_millis(10)));
+
+        // Frees still work, and the map drains
+        sb.put(bit, &mut hint);
+        assert!(sb.wait_idle(Duration::ZERO));
+        assert_eq!(sb.weight(), 0);
+
+        sb.unfreeze();
+        assert!(!sb.is_frozen());
+        assert!(sb.get(&mut hint).is_some());
+    }
+
+    #[test]
+    fn test_wait_idle_woken_by_put() {
+        let sb = Arc::new(Sbitmap::new(32, None, false));
+        let mut hint = 0;
+        let held: Vec<usize> = (0..8).map(|_| sb.get(&mut hint).unwrap()).collect();
+
+        sb.freeze();
+        let sb2 = Arc::clone(&sb);
+        let putter = thread::spawn(move || {
+            let mut hint = 0;
+            for bit in held {
+                thread::sleep(Duration::from_millis(1));
+                sb2.put(bit, &mut hint);
+            }
+        });
+
+        assert!(sb.wait_idle(Duration::MAX));
+        assert_eq!(sb.weight(), 0);
+        putter.join().unwrap();
+    }
+
+    #[test]
+    fn test_freeze_while_allocating() {
+        let sb = Arc::new(Sbitmap::new(256, None, false));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let sb = Arc::clone(&sb);
+                thread::spawn(move || {
+                    let mut hint = 0;
+                    let mut failures = 0;
+                    while failures < 1000 {
+                        match sb.get(&mut hint) {
+                            Some(bit) => sb.put(bit, &mut hint),
+                            None => failures += 1,
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        thread::sleep(Duration::from_millis(5));
+        sb.freeze();
+        assert!(sb.wait_idle(Duration::from_secs(10)));
+        assert_eq!(sb.weight(), 0);
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(sb.weight(), 0);
+    }
+}
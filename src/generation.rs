@@ -0,0 +1,240 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Generation-tagged handles
+//
+// A bit number alone cannot tell two owners of the same tag apart: a late
+// completion for a freed request would free the tag of whoever allocated
+// it next. `GenSbitmap` keeps a generation counter per bit, bumped on every
+// free, and only accepts a free whose handle carries the current one.
+
+use crate::Sbitmap;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// An allocated bit together with the generation it was allocated in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TagHandle {
+    /// The allocated bit number
+    pub bit: usize,
+    /// Generation of the bit when it was allocated
+    pub gen: u32,
+}
+
+/// Error returned when a handle does not name a bit it may free
+///
+/// In either case no bit is freed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleTag {
+    /// The handle's generation is out of date
+    ///
+    /// The bit has been freed since the handle was issued, and possibly
+    /// allocated again to another owner, which keeps it.
+    Outdated {
+        /// The rejected handle
+        handle: TagHandle,
+        /// Generation of the bit when the free was attempted
+        current: u32,
+    },
+    /// The handle's bit number is not below the depth
+    InvalidBit {
+        /// The rejected handle
+        handle: TagHandle,
+    },
+}
+
+impl StaleTag {
+    /// Get the rejected handle
+    pub fn handle(&self) -> TagHandle {
+        match *self {
+            Self::Outdated { handle, .. } | Self::InvalidBit { handle } => handle,
+        }
+    }
+}
+
+impl fmt::Display for StaleTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Outdated { handle, current } => write!(
+                f,
+                "stale free of tag {} (generation {}, current {})",
+                handle.bit, handle.gen, current
+            ),
+            Self::InvalidBit { handle } => write!(f, "free of invalid tag {}", handle.bit),
+        }
+    }
+}
+
+impl std::error::Error for StaleTag {}
+
+/// Sbitmap wrapper handing out generation-tagged handles
+///
+/// Each bit has a 32-bit generation, incremented (wrapping) when the bit
+/// is freed. `put()` bumps the generation with a compare-and-swap against
+/// the handle's, so of several frees of one handle only the first succeeds,
+/// and a free from a previous owner never touches the current one. A
+/// handle can only be confused with one issued 2^32 reuses later.
+///
+/// Costs 4 bytes per bit.
+pub struct GenSbitmap {
+    /// The underlying bitmap
+    map: Sbitmap,
+    /// Current generation of each bit
+    gens: Box<[AtomicU32]>,
+}
+
+impl GenSbitmap {
+    /// Wrap a bitmap, starting every bit at generation 0
+    ///
+    /// # Arguments
+    /// * `map` - The bitmap to allocate bits from
+    pub fn new(map: Sbitmap) -> Self {
+        let gens = (0..map.depth()).map(|_| AtomicU32::new(0)).collect();
+        Self { map, gens }
+    }
+
+    /// Allocate a bit and return a handle for it
+    ///
+    /// # Arguments
+    /// * `hint` - Mutable reference to caller's allocation hint
+    ///
+    /// # Returns
+    /// * `Some(handle)` - Successfully allocated bit and its generation
+    /// * `None` - No free bits available
+    pub fn get(&self, hint: &mut usize) -> Option<TagHandle> {
+        let bit = self.map.get(hint)?;
+        // The bit was acquired, so the previous owner's bump is visible
+        let gen = self.gens[bit].load(Ordering::Relaxed);
+        Some(TagHandle { bit, gen })
+    }
+
+    /// Free the bit of a handle if its generation is still current
+    ///
+    /// Unlike `Sbitmap::put()`, which ignores them, handles with an invalid
+    /// bit number are rejected.
+    ///
+    /// # Arguments
+    /// * `handle` - The handle returned by get()
+    /// * `hint` - Mutable reference to caller's allocation hint
+    ///
+    /// # Returns
+    /// * `Ok(())` - The bit was freed
+    /// * `Err(StaleTag::Outdated)` - The handle was already freed; the bit
+    ///   is left alone
+    /// * `Err(StaleTag::InvalidBit)` - The handle's bit is beyond the depth
+    pub fn put(&self, handle: TagHandle, hint: &mut usize) -> Result<(), StaleTag> {
+        let Some(gen) = self.gens.get(handle.bit) else {
+            let invalid = StaleTag::InvalidBit { handle };
+            log::debug!("sbitmap::generation: {invalid}");
+            return Err(invalid);
+        };
+
+        if let Err(current) = gen.compare_exchange(
+            handle.gen,
+            handle.gen.wrapping_add(1),
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            let stale = StaleTag::Outdated { handle, current };
+            log::debug!("sbitmap::generation: {stale}");
+            return Err(stale);
+        }
+
+        self.map.put(handle.bit, hint);
+        Ok(())
+    }
+
+    /// Check whether a handle is still the current owner of its bit
+    pub fn is_current(&self, handle: TagHandle) -> bool {
+        self.test_bit(handle.bit) && self.gens[handle.bit].load(Ordering::Relaxed) == handle.gen
+    }
+
+    /// Check if a specific bit is allocated
+    pub fn test_bit(&self, bitnr: usize) -> bool {
+        self.map.test_bit(bitnr)
+    }
+
+    /// Count the allocated bits
+    pub fn weight(&self) -> usize {
+        self.map.weight()
+    }
+
+    /// Get the total number of bits
+    pub fn depth(&self) -> usize {
+        self.map.depth()
+    }
+
+    /// Get the underlying bitmap
+    pub fn inner(&self) -> &Sbitmap {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_stale_put_refused() {
+        let sb = GenSbitmap::new(Sbitmap::new(1, None, false));
+        let mut hint = 0;
+
+        let first = sb.get(&mut hint).unwrap();
+        assert_eq!(first, TagHandle { bit: 0, gen: 0 });
+        assert!(sb.is_current(first));
+        assert_eq!(sb.put(first, &mut hint), Ok(()));
+        assert!(!sb.is_current(first));
+
+        // The bit is reused; a late free of the old handle must not free it
+        let second = sb.get(&mut hint).unwrap();
+        assert_eq!(second, TagHandle { bit: 0, gen: 1 });
+        let stale = sb.put(first, &mut hint).unwrap_err();
+        assert_eq!(
+            stale,
+            StaleTag::Outdated {
+                handle: first,
+                current: 1
+            }
+        );
+        assert_eq!(stale.handle(), first);
+        assert!(sb.test_bit(0));
+        assert!(sb.is_current(second));
+
+        // Double free of the current handle
+        assert_eq!(sb.put(second, &mut hint), Ok(()));
+        assert!(sb.put(second, &mut hint).is_err());
+        assert_eq!(sb.weight(), 0);
+
+        // Out of range handles are rejected
+        let invalid = TagHandle { bit: 5, gen: 0 };
+        assert_eq!(
+            sb.put(invalid, &mut hint),
+            Err(StaleTag::InvalidBit { handle: invalid })
+        );
+        assert_eq!(sb.weight(), 0);
+    }
+
+    #[test]
+    fn test_concurrent_double_free() {
+        let sb = Arc::new(GenSbitmap::new(Sbitmap::new(16, None, false)));
+        let mut hint = 0;
+
+        for _ in 0..200 {
+            let handle = sb.get(&mut hint).unwrap();
+            let frees: Vec<_> = (0..2)
+                .map(|_| {
+                    let sb = Arc::clone(&sb);
+                    thread::spawn(move || sb.put(handle, &mut 0).is_ok())
+                })
+                .collect();
+            let succeeded = frees
+                .into_iter()
+                .map(|t| t.join().unwrap())
+                .filter(|&ok| ok)
+                .count();
+            assert_eq!(succeeded, 1);
+            assert_eq!(sb.weight(), 0);
+        }
+    }
+}
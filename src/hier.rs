@@ -0,0 +1,389 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Hierarchical sbitmap for very large depths
+//
+// `Sbitmap::get()` scans words one by one, which is fine for tag maps of a
+// few thousand bits but costs thousands of cache-line loads on a nearly
+// full map of a million bits. `HierSbitmap` adds summary levels on top of
+// the leaf words: bit `j` of summary word `i` is set when word
+// `i * BITS_PER_WORD + j` of the level below is full. A search descends
+// through words with clear bits only, so it touches O(log n) words.
+
+use crate::{TagAllocator, BITS_PER_WORD};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Word with every bit set: all bits allocated, or all children full
+const FULL: usize = usize::MAX;
+
+/// Bitmap with per-group "full" summary words for fast search at scale
+///
+/// Level 0 holds the bits themselves, densely packed. Every higher level
+/// has one bit per word of the level below, and the top level is a single
+/// word. Bits past `depth` in the last word of each level are set at
+/// creation, so a word is full exactly when it equals `usize::MAX`.
+///
+/// Every level uses `AtomicUsize` words; unlike `Sbitmap<W>`, the word
+/// type is not configurable. Summary levels pack one bit per child word,
+/// so wider words would only shave a level off the largest maps.
+///
+/// Summary bits are maintained with sequentially consistent operations:
+/// `get()` sets a summary bit and then re-checks the child word, and
+/// `put()` clears the leaf bit before clearing summary bits, so a summary
+/// bit never stays set once the word below has a free bit. While the two
+/// race, a search may skip a word that just got a free bit, the same way
+/// `Sbitmap::get()` may miss a bit freed behind its scan.
+pub struct HierSbitmap {
+    /// Total number of bits in the bitmap
+    depth: usize,
+    /// Words of each level, leaves first, a single top word last
+    levels: Vec<Box<[AtomicUsize]>>,
+}
+
+impl HierSbitmap {
+    /// Create a hierarchical bitmap with the specified depth
+    ///
+    /// # Arguments
+    /// * `depth` - Total number of bits to allocate
+    pub fn new(depth: usize) -> Self {
+        let mut levels = Vec::new();
+        let mut nr_bits = depth;
+        loop {
+            let nr_words = nr_bits.div_ceil(BITS_PER_WORD).max(1);
+            let words: Box<[AtomicUsize]> = (0..nr_words)
+                .map(|i| {
+                    // Pre-set the bits past the end of the level
+                    let valid = nr_bits.saturating_sub(i * BITS_PER_WORD);
+                    let word = if valid >= BITS_PER_WORD {
+                        0
+                    } else {
+                        FULL << valid
+                    };
+                    AtomicUsize::new(word)
+                })
+                .collect();
+            levels.push(words);
+            if nr_words == 1 {
+                break;
+            }
+            nr_bits = nr_words;
+        }
+
+        log::debug!(
+            "sbitmap::hier: depth={depth}, levels={}, leaf_words={}",
+            levels.len(),
+            levels[0].len()
+        );
+
+        Self { depth, levels }
+    }
+
+    /// Get the number of levels, including the leaf level
+    pub fn nr_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Allocate a free bit
+    ///
+    /// The search starts at the caller's hint and only visits words whose
+    /// summary bit says they have free bits, so it stays close to O(log n)
+    /// at any occupancy. This operation provides acquire barrier semantics
+    /// on success.
+    ///
+    /// # Arguments
+    /// * `hint` - Mutable reference to caller's allocation hint
+    ///
+    /// # Returns
+    /// * `Some(bit_number)` - Successfully allocated bit number
+    /// * `None` - No free bits available
+    pub fn get(&self, hint: &mut usize) -> Option<usize> {
+        if *hint >= self.depth {
+            *hint = 0;
+        }
+
+        let top = self.levels.len() - 1;
+        match self.find(top, 0, *hint) {
+            Some(bit) => {
+                *hint = if bit + 1 >= self.depth { 0 } else { bit + 1 };
+                Some(bit)
+            }
+            None => {
+                *hint = 0;
+                None
+            }
+        }
+    }
+
+    /// Search word `index` of `level` for a free leaf bit
+    ///
+    /// Children are visited starting from the one on the path to `hint`,
+    /// wrapping around within the word.
+    fn find(&self, level: usize, index: usize, hint: usize) -> Option<usize> {
+        let word = &self.levels[level][index];
+
+        // Position of hint's ancestor in this level, if it is in this word
+        let hint_pos = hint >> (level * BITS_PER_WORD.trailing_zeros() as usize);
+        let start = if hint_pos / BITS_PER_WORD == index {
+            hint_pos % BITS_PER_WORD
+        } else {
+            0
+        };
+
+        let mut val = word.load(Ordering::SeqCst);
+        while let Some(offset) = Self::next_clear(val, start) {
+            let child = index * BITS_PER_WORD + offset;
+
+            if level == 0 {
+                let mask = 1usize << offset;
+                let prev = word.fetch_or(mask, Ordering::SeqCst);
+                if prev & mask == 0 {
+                    if prev | mask == FULL {
+                        self.mark_full(1, index);
+                    }
+                    return Some(child);
+                }
+                val = prev | mask;
+                continue;
+            }
+
+            if let Some(bit) = self.find(level - 1, child, hint) {
+                return Some(bit);
+            }
+
+            // The child was full but not marked yet, fix the summary
+            self.mark_full(level, child);
+            val = word.load(Ordering::SeqCst) | (1usize << offset);
+        }
+
+        None
+    }
+
+    /// Find the first clear bit of `val` at or after `start`, wrapping
+    #[inline]
+    fn next_clear(val: usize, start: usize) -> Option<usize> {
+        let free = !val;
+        if free == 0 {
+            return None;
+        }
+        let upper = free & (FULL << start);
+        let pick = if upper != 0 { upper } else { free };
+        Some(pick.trailing_zeros() as usize)
+    }
+
+    /// Record in `level` that word `index` of the level below is full
+    fn mark_full(&self, level: usize, index: usize) {
+        if level == self.levels.len() {
+            return;
+        }
+
+        let mask = 1usize << (index % BITS_PER_WORD);
+        let parent = index / BITS_PER_WORD;
+        let prev = self.levels[level][parent].fetch_or(mask, Ordering::SeqCst);
+
+        // A put may have cleared a child bit before the summary bit was
+        // set; if so, undo it
+        if self.levels[level - 1][index].load(Ordering::SeqCst) != FULL {
+            self.clear_full(level, index);
+            return;
+        }
+
+        if prev != FULL && prev | mask == FULL {
+            self.mark_full(level + 1, parent);
+        }
+    }
+
+    /// Record in `level` that word `index` of the level below has a free bit
+    fn clear_full(&self, level: usize, index: usize) {
+        if level == self.levels.len() {
+            return;
+        }
+
+        let mask = 1usize << (index % BITS_PER_WORD);
+        let parent = index / BITS_PER_WORD;
+        let prev = self.levels[level][parent].fetch_and(!mask, Ordering::SeqCst);
+        if prev == FULL {
+            self.clear_full(level + 1, parent);
+        }
+    }
+
+    /// Free a previously allocated bit
+    ///
+    /// This operation provides release barrier semantics.
+    ///
+    /// # Arguments
+    /// * `bitnr` - The bit number to free (must have been returned by get())
+    /// * `hint` - Mutable reference to caller's allocation hint
+    pub fn put(&self, bitnr: usize, hint: &mut usize) {
+        if bitnr >= self.depth {
+            return; // Invalid bit number
+        }
+
+        let index = bitnr / BITS_PER_WORD;
+        let mask = 1usize << (bitnr % BITS_PER_WORD);
+        let prev = self.levels[0][index].fetch_and(!mask, Ordering::SeqCst);
+        if prev == FULL {
+            self.clear_full(1, index);
+        }
+
+        *hint = bitnr;
+    }
+
+    /// Check if a specific bit is set (allocated)
+    pub fn test_bit(&self, bitnr: usize) -> bool {
+        if bitnr >= self.depth {
+            return false;
+        }
+        let word = self.levels[0][bitnr / BITS_PER_WORD].load(Ordering::Relaxed);
+        word & (1usize << (bitnr % BITS_PER_WORD)) != 0
+    }
+
+    /// Count the number of allocated (set) bits
+    pub fn weight(&self) -> usize {
+        let set: usize = self.levels[0]
+            .iter()
+            .map(|w| w.load(Ordering::Relaxed).count_ones() as usize)
+            .sum();
+        // Discount the pre-set bits past the end
+        set - (self.levels[0].len() * BITS_PER_WORD - self.depth)
+    }
+
+    /// Get the total number of bits in the bitmap
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+impl TagAllocator for HierSbitmap {
+    #[inline]
+    fn get(&self, hint: &mut usize) -> Option<usize> {
+        HierSbitmap::get(self, hint)
+    }
+
+    #[inline]
+    fn put(&self, tag: usize, hint: &mut usize) {
+        HierSbitmap::put(self, tag, hint)
+    }
+
+    #[inline]
+    fn depth(&self) -> usize {
+        HierSbitmap::depth(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_hier_levels() {
+        assert_eq!(HierSbitmap::new(0).nr_levels(), 1);
+        assert_eq!(HierSbitmap::new(BITS_PER_WORD).nr_levels(), 1);
+        assert_eq!(HierSbitmap::new(BITS_PER_WORD + 1).nr_levels(), 2);
+        assert_eq!(
+            HierSbitmap::new(BITS_PER_WORD * BITS_PER_WORD + 1).nr_levels(),
+            3
+        );
+
+        let mut hint = 0;
+        let empty = HierSbitmap::new(0);
+        assert_eq!(empty.get(&mut hint), None);
+        assert_eq!(empty.weight(), 0);
+    }
+
+    #[test]
+    fn test_hier_exhaustion_and_reuse() {
+        // Three levels with partial words at every level
+        let depth = BITS_PER_WORD * BITS_PER_WORD + 3 * BITS_PER_WORD + 5;
+        let sb = HierSbitmap::new(depth);
+        assert_eq!(sb.nr_levels(), 3);
+
+        let mut hint = 0;
+        let mut bits: Vec<usize> = std::iter::from_fn(|| sb.get(&mut hint)).collect();
+        assert_eq!(bits.len(), depth);
+        assert_eq!(sb.weight(), depth);
+        let top = sb.levels.last().unwrap()[0].load(Ordering::Relaxed);
+        assert_eq!(top, FULL);
+
+        bits.sort_unstable();
+        assert_eq!(bits, (0..depth).collect::<Vec<_>>());
+
+        // A single free bit deep in the map is found from any hint
+        let free = depth - 2;
+        sb.put(free, &mut hint);
+        assert!(!sb.test_bit(free));
+        let mut other = 0;
+        assert_eq!(sb.get(&mut other), Some(free));
+        assert_eq!(sb.get(&mut other), None);
+
+        for bit in bits {
+            sb.put(bit, &mut hint);
+        }
+        assert_eq!(sb.weight(), 0);
+
+        // Summaries are back to their initial state
+        let fresh = HierSbitmap::new(depth);
+        for (level, words) in sb.levels.iter().enumerate() {
+            for (i, word) in words.iter().enumerate() {
+                assert_eq!(
+                    word.load(Ordering::Relaxed),
+                    fresh.levels[level][i].load(Ordering::Relaxed)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_hier_hint() {
+        let sb = HierSbitmap::new(4 * BITS_PER_WORD);
+        let mut hint = 2 * BITS_PER_WORD + 7;
+        assert_eq!(sb.get(&mut hint), Some(2 * BITS_PER_WORD + 7));
+        assert_eq!(hint, 2 * BITS_PER_WORD + 8);
+
+        // Hints past the end wrap to the start
+        let mut hint = 4 * BITS_PER_WORD;
+        assert_eq!(sb.get(&mut hint), Some(0));
+    }
+
+    #[test]
+    fn test_hier_concurrent() {
+        let depth = 20_000;
+        let sb = Arc::new(HierSbitmap::new(depth));
+
+        // Keep the map nearly full so searches go through the summaries
+        let mut hint = 0;
+        let held: Vec<usize> = (0..depth - 64).filter_map(|_| sb.get(&mut hint)).collect();
+        assert_eq!(held.len(), depth - 64);
+
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let sb = Arc::clone(&sb);
+                thread::spawn(move || {
+                    let mut hint = t * depth / 4;
+                    for _ in 0..2000 {
+                        let mut got = Vec::new();
+                        for _ in 0..8 {
+                            if let Some(bit) = sb.get(&mut hint) {
+                                assert!(sb.test_bit(bit));
+                                got.push(bit);
+                            }
+                        }
+                        for bit in got {
+                            sb.put(bit, &mut hint);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(sb.weight(), depth - 64);
+
+        // Every free bit is still reachable through the summaries
+        let mut hint = 0;
+        let rest: Vec<usize> = std::iter::from_fn(|| sb.get(&mut hint)).collect();
+        assert_eq!(rest.len(), 64);
+    }
+}
@@ -0,0 +1,229 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Tag leases with allocation timestamps
+//
+// A tag that is never returned hangs whoever waits for it. `LeaseSbitmap`
+// records when each tag was allocated in side storage next to the bitmap,
+// so a periodic timeout handler can find tags held for too long, like
+// blk-mq's request timeout scan.
+
+use crate::{Sbitmap, TagAllocator};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Sbitmap wrapper that timestamps every allocated tag
+///
+/// Each tag has a lease stamp: the allocation time in nanoseconds since
+/// the wrapper was created, plus one so that 0 means "not held". `get()`
+/// sets the stamp after allocating the bit and `put()` clears it before
+/// freeing the bit, so a set stamp always belongs to a held tag.
+///
+/// Costs 8 bytes per tag and one clock read per `get()`.
+pub struct LeaseSbitmap {
+    /// The underlying bitmap
+    map: Sbitmap,
+    /// Lease stamp of each tag, 0 if not held
+    stamps: Box<[AtomicU64]>,
+    /// Time that stamps are measured from
+    origin: Instant,
+}
+
+impl LeaseSbitmap {
+    /// Wrap a bitmap, tracking tags allocated from now on
+    ///
+    /// Bits already allocated in `map` have no lease and are never
+    /// reported as expired.
+    ///
+    /// # Arguments
+    /// * `map` - The bitmap to allocate tags from
+    pub fn new(map: Sbitmap) -> Self {
+        let stamps = (0..map.depth()).map(|_| AtomicU64::new(0)).collect();
+        Self {
+            map,
+            stamps,
+            origin: Instant::now(),
+        }
+    }
+
+    /// Time `at` as a lease stamp
+    #[inline]
+    fn stamp_at(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.origin).as_nanos() as u64 + 1
+    }
+
+    /// Current time as a lease stamp
+    #[inline]
+    fn now(&self) -> u64 {
+        self.stamp_at(Instant::now())
+    }
+
+    /// Allocate a tag and start its lease
+    ///
+    /// # Arguments
+    /// * `hint` - Mutable reference to caller's allocation hint
+    ///
+    /// # Returns
+    /// * `Some(tag)` - Successfully allocated tag
+    /// * `None` - No free tags available
+    pub fn get(&self, hint: &mut usize) -> Option<usize> {
+        let tag = self.map.get(hint)?;
+        self.stamps[tag].store(self.now(), Ordering::Relaxed);
+        Some(tag)
+    }
+
+    /// End a tag's lease and free it
+    ///
+    /// # Arguments
+    /// * `tag` - The tag to free (must have been returned by get())
+    /// * `hint` - Mutable reference to caller's allocation hint
+    pub fn put(&self, tag: usize, hint: &mut usize) {
+        let Some(stamp) = self.stamps.get(tag) else {
+            return; // Invalid tag
+        };
+        stamp.store(0, Ordering::Relaxed);
+        self.map.put(tag, hint);
+    }
+
+    /// How long a tag has been held
+    ///
+    /// # Returns
+    /// * `Some(duration)` - Time since the tag was allocated
+    /// * `None` - The tag is not held, or was allocated before tracking began
+    pub fn held_for(&self, tag: usize) -> Option<Duration> {
+        let stamp = self.stamps.get(tag)?.load(Ordering::Relaxed);
+        if stamp == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos(self.now().saturating_sub(stamp)))
+    }
+
+    /// Iterate over tags held for at least `older_than`
+    ///
+    /// Tags are listed in increasing order. The scan runs concurrently with
+    /// `get()` and `put()`: a tag freed while it runs may still be listed,
+    /// so a timeout handler must check the tag's owner before acting on it.
+    ///
+    /// # Arguments
+    /// * `older_than` - Minimum time a tag must have been held
+    pub fn expired(&self, older_than: Duration) -> impl Iterator<Item = usize> + '_ {
+        self.expired_at(Instant::now(), older_than)
+    }
+
+    /// Iterate over tags held for at least `older_than` at time `now`
+    ///
+    /// Like `expired()`, but against a caller-supplied clock reading, e.g.
+    /// to scan several bitmaps against the same instant.
+    ///
+    /// # Arguments
+    /// * `now` - The time to measure lease ages at
+    /// * `older_than` - Minimum time a tag must have been held
+    pub fn expired_at(
+        &self,
+        now: Instant,
+        older_than: Duration,
+    ) -> impl Iterator<Item = usize> + '_ {
+        let now = self.stamp_at(now);
+        let min_age = u64::try_from(older_than.as_nanos()).unwrap_or(u64::MAX);
+
+        self.stamps
+            .iter()
+            .enumerate()
+            .filter_map(move |(tag, stamp)| {
+                let stamp = stamp.load(Ordering::Relaxed);
+                (stamp != 0 && now.saturating_sub(stamp) >= min_age).then_some(tag)
+            })
+    }
+
+    /// Check if a specific tag is allocated
+    pub fn test_bit(&self, tag: usize) -> bool {
+        self.map.test_bit(tag)
+    }
+
+    /// Count the allocated tags
+    pub fn weight(&self) -> usize {
+        self.map.weight()
+    }
+
+    /// Get the total number of tags
+    pub fn depth(&self) -> usize {
+        self.map.depth()
+    }
+
+    /// Get the underlying bitmap
+    pub fn inner(&self) -> &Sbitmap {
+        &self.map
+    }
+
+    /// Stop tracking and return the underlying bitmap
+    pub fn into_inner(self) -> Sbitmap {
+        self.map
+    }
+}
+
+impl TagAllocator for LeaseSbitmap {
+    #[inline]
+    fn get(&self, hint: &mut usize) -> Option<usize> {
+        LeaseSbitmap::get(self, hint)
+    }
+
+    #[inline]
+    fn put(&self, tag: usize, hint: &mut usize) {
+        LeaseSbitmap::put(self, tag, hint)
+    }
+
+    #[inline]
+    fn depth(&self) -> usize {
+        LeaseSbitmap::depth(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_tracking() {
+        let leases = LeaseSbitmap::new(Sbitmap::new(16, None, false));
+        let mut hint = 0;
+        let timeout = Duration::from_secs(1);
+
+        let old: Vec<usize> = (0..3).map(|_| leases.get(&mut hint).unwrap()).collect();
+        // Wait for the clock to tick, so that `new` is stamped after `split`
+        let split = Instant::now();
+        while Instant::now() <= split {}
+        let new = leases.get(&mut hint).unwrap();
+
+        // One timeout after the split, only the tags from before it expired
+        let scan = split + timeout;
+        let expired: Vec<usize> = leases.expired_at(scan, timeout).collect();
+        assert_eq!(expired, old);
+        assert!(leases.held_for(new).is_some());
+
+        // Everything held counts for a zero timeout, nothing for a huge one
+        assert_eq!(leases.expired_at(scan, Duration::ZERO).count(), 4);
+        assert_eq!(leases.expired_at(scan, Duration::MAX).count(), 0);
+        assert_eq!(leases.expired(Duration::ZERO).count(), 4);
+
+        leases.put(old[1], &mut hint);
+        assert_eq!(leases.held_for(old[1]), None);
+        let expired: Vec<usize> = leases.expired_at(scan, timeout).collect();
+        assert_eq!(expired, [old[0], old[2]]);
+        assert_eq!(leases.weight(), 3);
+    }
+
+    #[test]
+    fn test_lease_untracked_bits() {
+        let map = Sbitmap::new(8, None, false);
+        let mut hint = 0;
+        let before = map.get(&mut hint).unwrap();
+
+        let leases = LeaseSbitmap::new(map);
+        assert!(leases.test_bit(before));
+        assert_eq!(leases.held_for(before), None);
+        assert_eq!(leases.expired(Duration::ZERO).count(), 0);
+        assert_eq!(leases.held_for(100), None);
+
+        leases.put(before, &mut hint);
+        assert_eq!(leases.into_inner().weight(), 0);
+    }
+}
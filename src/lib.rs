@@ -5,32 +5,60 @@
 // This module provides lock-free, cache-line optimized bitmap allocation
 // designed for high-concurrency scenarios like IO tag allocation.
 
+// Bitmap words use loom's atomics under `--cfg loom` so that the model
+// tests in tests/loom.rs can explore every interleaving of get/put.
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(not(loom))]
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-/// Cache line size for modern x86_64/aarch64 processors
-const CACHE_LINE_SIZE: usize = 64;
-
-/// Bits per word (typically 64 on 64-bit systems)
+use freeze::FreezeState;
+
+mod allocator;
+mod cache;
+mod freeze;
+mod generation;
+mod hier;
+mod lease;
+mod numa;
+mod policy;
+mod random;
+mod sharded;
+mod slab;
+mod snapshot;
+mod word;
+
+pub use allocator::TagAllocator;
+pub use cache::TagCache;
+pub use generation::{GenSbitmap, StaleTag, TagHandle};
+pub use hier::HierSbitmap;
+pub use lease::LeaseSbitmap;
+pub use policy::AllocPolicy;
+pub use random::{RandomSource, SplitMix64};
+pub use sharded::ShardedSbitmap;
+pub use slab::{Key, TagSlab};
+pub use snapshot::{SbitmapSnapshot, SnapshotError};
+pub use word::{BitmapWord, WordBits};
+
+/// Bits per word of the default `AtomicUsize` words (typically 64)
 const BITS_PER_WORD: usize = usize::BITS as usize;
 
 /// Cache-line aligned bitmap word to prevent false sharing
 ///
 /// Each word is placed on its own cache line to ensure that concurrent
-/// operations on different words don't cause cache line ping-pong.
+/// operations on different words don't cause cache line ping-pong. The
+/// alignment pads the struct to a full cache line for any word width.
 #[repr(align(64))]
-struct SbitmapWord {
+struct SbitmapWord<W> {
     /// Atomic bitmap word - bits set to 1 are allocated, 0 are free
-    word: AtomicUsize,
-    /// Padding to fill the cache line
-    _padding: [u8; CACHE_LINE_SIZE - std::mem::size_of::<AtomicUsize>()],
+    word: W,
 }
 
-impl SbitmapWord {
+impl<W: BitmapWord> SbitmapWord<W> {
     /// Create a new sbitmap word with all bits free
     fn new() -> Self {
         Self {
-            word: AtomicUsize::new(0),
-            _padding: [0; CACHE_LINE_SIZE - std::mem::size_of::<AtomicUsize>()],
+            word: W::new(W::Bits::ZERO),
         }
     }
 }
@@ -40,7 +68,11 @@ impl SbitmapWord {
 /// The bitmap is spread across multiple cache lines to reduce contention
 /// in multi-threaded scenarios. Each task maintains its own allocation
 /// hint to start searching from different positions.
-pub struct Sbitmap {
+///
+/// Bits are stored in atomic words of type `W`, `AtomicUsize` by default.
+/// A word holds at most `W::Bits::BITS` bits, which bounds `shift` and the
+/// largest `get_batch()`.
+pub struct Sbitmap<W: BitmapWord = AtomicUsize> {
     /// Total number of bits in the bitmap
     depth: usize,
     /// log2(bits per word) - used for fast division/modulo
@@ -48,9 +80,11 @@ pub struct Sbitmap {
     /// Number of words in the bitmap
     map_nr: usize,
     /// Array of cache-line aligned bitmap words
-    map: Vec<SbitmapWord>,
-    /// Whether to use strict round-robin allocation
-    round_robin: bool,
+    map: Vec<SbitmapWord<W>>,
+    /// Order in which bits are handed out
+    policy: AllocPolicy,
+    /// Freeze flag and `wait_idle()` waiters
+    freeze: FreezeState,
 }
 
 impl Sbitmap {
@@ -64,14 +98,75 @@ impl Sbitmap {
     /// # Returns
     /// A new Sbitmap instance
     pub fn new(depth: usize, shift: Option<u32>, round_robin: bool) -> Self {
-        let shift = shift.unwrap_or_else(|| Self::calculate_shift(depth));
+        Self::with_word_type(depth, shift, round_robin)
+    }
+
+    /// Create a new sbitmap with the specified allocation policy
+    ///
+    /// `new(depth, shift, round_robin)` is the same as this with
+    /// `AllocPolicy::RoundRobin` if `round_robin` is set and
+    /// `AllocPolicy::Hint` otherwise.
+    ///
+    /// # Arguments
+    /// * `depth` - Total number of bits to allocate
+    /// * `shift` - Optional log2(bits per word). If None, a sensible default is chosen
+    /// * `policy` - Order in which `get()` and `get_batch()` hand out bits
+    ///
+    /// # Returns
+    /// A new Sbitmap instance
+    pub fn with_policy(depth: usize, shift: Option<u32>, policy: AllocPolicy) -> Self {
+        Self::with_word_type_and_policy(depth, shift, policy)
+    }
+}
+
+impl<W: BitmapWord> Sbitmap<W> {
+    /// Create a new sbitmap storing its bits in words of type `W`
+    ///
+    /// Use as `Sbitmap::<AtomicU32>::with_word_type(...)`. A `shift` larger
+    /// than log2(`W::Bits::BITS`) is clamped to it.
+    ///
+    /// # Arguments
+    /// * `depth` - Total number of bits to allocate
+    /// * `shift` - Optional log2(bits per word). If None, a sensible default is chosen
+    /// * `round_robin` - If true, use strict round-robin allocation order
+    ///
+    /// # Returns
+    /// A new Sbitmap instance
+    pub fn with_word_type(depth: usize, shift: Option<u32>, round_robin: bool) -> Self {
+        let policy = if round_robin {
+            AllocPolicy::RoundRobin
+        } else {
+            AllocPolicy::Hint
+        };
+        Self::with_word_type_and_policy(depth, shift, policy)
+    }
+
+    /// Create a new sbitmap with words of type `W` and an allocation policy
+    ///
+    /// # Arguments
+    /// * `depth` - Total number of bits to allocate
+    /// * `shift` - Optional log2(bits per word). If None, a sensible default is chosen
+    /// * `policy` - Order in which `get()` and `get_batch()` hand out bits
+    ///
+    /// # Returns
+    /// A new Sbitmap instance
+    pub fn with_word_type_and_policy(
+        depth: usize,
+        shift: Option<u32>,
+        policy: AllocPolicy,
+    ) -> Self {
+        let max_shift = W::Bits::BITS.trailing_zeros();
+        let shift = shift
+            .unwrap_or_else(|| Self::calculate_shift(depth))
+            .min(max_shift);
         let bits_per_word = 1usize << shift;
         let map_nr = depth.div_ceil(bits_per_word);
 
         let map = (0..map_nr).map(|_| SbitmapWord::new()).collect();
 
         log::debug!(
-            "sbitmap::new: depth={depth}, shift={shift}, map_nr={map_nr}, bits_per_word={bits_per_word}, round_robin={round_robin}"
+            "sbitmap::new: depth={depth}, shift={shift}, map_nr={map_nr}, bits_per_word={bits_per_word}, word_bits={}, policy={policy:?}",
+            W::Bits::BITS
         );
 
         Self {
@@ -79,7 +174,8 @@ impl Sbitmap {
             shift,
             map_nr,
             map,
-            round_robin,
+            policy,
+            freeze: FreezeState::new(),
         }
     }
 
@@ -88,7 +184,7 @@ impl Sbitmap {
     /// This follows the kernel's heuristic: for small bitmaps, use fewer
     /// bits per word to spread across more cache lines for better parallelism.
     fn calculate_shift(depth: usize) -> u32 {
-        let mut shift = BITS_PER_WORD.trailing_zeros();
+        let mut shift = W::Bits::BITS.trailing_zeros();
 
         // If the bitmap is small, shrink the number of bits per word so
         // we spread over a few cachelines, at least. If less than 4
@@ -129,18 +225,17 @@ impl Sbitmap {
     ///
     /// This is equivalent to kernel's find_next_zero_bit within a word.
     #[inline]
-    fn find_next_zero_bit(word: usize, depth: usize, hint: usize) -> Option<usize> {
-        // Mask off bits before hint
-        let mask = !((1usize << hint).wrapping_sub(1));
-        let word = word | !mask;
+    fn find_next_zero_bit(word: W::Bits, depth: usize, hint: usize) -> Option<usize> {
+        // Mask off bits before hint (all of them if hint == word width)
+        let word = word | Self::make_mask(hint.min(W::Bits::BITS));
 
         // Find first zero bit
         let inverted = !word;
-        if inverted == 0 {
+        if inverted == W::Bits::ZERO {
             return None;
         }
 
-        let bit = inverted.trailing_zeros() as usize;
+        let bit = inverted.trailing_zeros();
         if bit < depth {
             Some(bit)
         } else {
@@ -151,22 +246,27 @@ impl Sbitmap {
     /// Create a bit mask for nr_bits
     ///
     /// Returns a mask with nr_bits set to 1.
-    /// Handles the special case when nr_bits == BITS_PER_WORD to avoid shift overflow.
+    /// Handles the special case when nr_bits is the word width to avoid shift overflow.
     #[inline]
-    fn make_mask(nr_bits: usize) -> usize {
-        if nr_bits == BITS_PER_WORD {
-            usize::MAX
+    fn make_mask(nr_bits: usize) -> W::Bits {
+        if nr_bits >= W::Bits::BITS {
+            W::Bits::MAX
         } else {
-            (1usize << nr_bits).wrapping_sub(1)
+            !(W::Bits::MAX << nr_bits)
         }
     }
 
     /// Find nr_bits consecutive zero bits in a word starting from hint
     ///
     /// Returns the starting position if found, None otherwise.
+    ///
+    /// Instead of testing a mask at every start offset, the free bits are
+    /// folded onto themselves: after `free &= free >> step` with
+    /// `step <= len`, bit i is set iff the `len + step` bits from i are all
+    /// free. Doubling `len` this way takes O(log nr_bits) word operations.
     #[inline]
     fn find_next_zero_batch(
-        word: usize,
+        word: W::Bits,
         depth: usize,
         hint: usize,
         nr_bits: usize,
@@ -174,40 +274,53 @@ impl Sbitmap {
         if depth < nr_bits || hint > depth.saturating_sub(nr_bits) {
             return None;
         }
+        if nr_bits == 0 {
+            return Some(hint);
+        }
 
-        let mask = Self::make_mask(nr_bits);
+        // Free bits in [hint, depth); zeros shifted in from the top keep
+        // runs from extending past depth
+        let mut free = !word & Self::make_mask(depth) & !Self::make_mask(hint);
 
-        for start in hint..=(depth - nr_bits) {
-            let bits_mask = mask << start;
-            if (word & bits_mask) == 0 {
-                return Some(start);
-            }
+        let mut len = 1;
+        while len < nr_bits && free != W::Bits::ZERO {
+            let step = len.min(nr_bits - len);
+            free = free & (free >> step);
+            len += step;
         }
 
-        None
+        if free == W::Bits::ZERO {
+            None
+        } else {
+            Some(free.trailing_zeros())
+        }
     }
 
     /// Atomically test and set a bit (acquire semantics)
     ///
     /// Returns true if the bit was successfully allocated (was 0, now 1)
+    ///
+    /// Bit-word RMWs are SeqCst rather than Acquire/Release so that they
+    /// order against the freeze flag and the fence in `wait_idle()` without
+    /// a fence of their own. A locked RMW is a full barrier on x86 anyway.
     #[inline]
-    fn test_and_set_bit_lock(&self, bit_offset: usize, word: &AtomicUsize) -> bool {
-        let mask = 1usize << bit_offset;
-        let old = word.fetch_or(mask, Ordering::Acquire);
-        (old & mask) == 0 // true if bit was previously 0
+    fn test_and_set_bit_lock(&self, bit_offset: usize, word: &W) -> bool {
+        let mask = W::Bits::ONE << bit_offset;
+        let old = word.fetch_or(mask, Ordering::SeqCst);
+        (old & mask) == W::Bits::ZERO // true if bit was previously 0
     }
 
     /// Atomically clear a bit (release semantics)
     #[inline]
-    fn clear_bit(&self, bit_offset: usize, word: &AtomicUsize) {
-        let mask = !(1usize << bit_offset);
-        word.fetch_and(mask, Ordering::Release);
+    fn clear_bit(&self, bit_offset: usize, word: &W) {
+        let mask = !(W::Bits::ONE << bit_offset);
+        word.fetch_and(mask, Ordering::SeqCst);
     }
 
     /// Try to allocate a bit from a specific word
     fn get_from_word(
         &self,
-        word: &AtomicUsize,
+        word: &W,
         depth: usize,
         alloc_hint: usize,
         wrap: bool,
@@ -238,7 +351,9 @@ impl Sbitmap {
                 return Some(nr);
             }
 
-            // Bit was already set, continue searching
+            // Bit was already set, continue searching. Like the kernel,
+            // restart from 0 instead of retrying the last bit; the rescan
+            // from 0 still reaches it if it is free.
             hint = nr + 1;
             if hint >= depth - 1 {
                 hint = 0;
@@ -247,9 +362,13 @@ impl Sbitmap {
     }
 
     /// Try to allocate nr_bits consecutive bits from a specific word
+    ///
+    /// The range is claimed with a compare-and-swap against the word value
+    /// it was found in, so a lost race never leaves behind bits that this
+    /// call set but does not own.
     fn get_batch_from_word(
         &self,
-        word: &AtomicUsize,
+        word: &W,
         depth: usize,
         alloc_hint: usize,
         nr_bits: usize,
@@ -261,11 +380,12 @@ impl Sbitmap {
 
         let mut hint = alloc_hint;
         let wrap = wrap && hint > 0; // don't wrap if starting from 0
+        let mask = Self::make_mask(nr_bits);
 
-        loop {
-            // Read current word value
-            let current = word.load(Ordering::Relaxed);
+        // Read current word value
+        let mut current = word.load(Ordering::Relaxed);
 
+        loop {
             // Find nr_bits consecutive zero bits starting from hint
             let nr = match Self::find_next_zero_batch(current, depth, hint, nr_bits) {
                 Some(bit) => bit,
@@ -280,20 +400,18 @@ impl Sbitmap {
                 }
             };
 
-            // Try to atomically set all nr_bits bits
-            let mask = Self::make_mask(nr_bits);
+            // Try to atomically set all nr_bits bits, only if the word
+            // has not changed since the range was found to be free
             let bits_mask = mask << nr;
-            let old = word.fetch_or(bits_mask, Ordering::Acquire);
-
-            // Check if all bits were zero before we set them
-            if (old & bits_mask) == 0 {
-                return Some(nr);
-            }
-
-            // Some bits were already set, continue searching from next position
-            hint = nr + 1;
-            if hint > depth.saturating_sub(nr_bits) {
-                hint = 0;
+            match word.compare_exchange_weak(
+                current,
+                current | bits_mask,
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(nr),
+                // Word changed under us, search again with the fresh value
+                Err(actual) => current = actual,
             }
         }
     }
@@ -370,38 +488,14 @@ impl Sbitmap {
             *hint = 0;
         }
 
-        let h = *hint;
-        let index = self.bit_to_index(h);
-
-        // Calculate bit offset within the word
-        let alloc_hint = if self.round_robin {
-            self.bit_to_offset(h)
-        } else {
-            0
-        };
-
-        let allocated = self.find_bit(index, alloc_hint, !self.round_robin);
-
-        // Update hint based on allocation result
-        match allocated {
-            None => {
-                // Map is full, reset hint to 0
-                *hint = 0;
-            }
-            Some(nr) if nr == h || self.round_robin => {
-                // Only update if we used the hint or in round-robin mode
-                let next_hint = nr + 1;
-                *hint = if next_hint >= self.depth {
-                    0
-                } else {
-                    next_hint
-                };
-            }
-            _ => {
-                // Don't update hint if we didn't use it
-            }
+        if self.depth == 0 {
+            return None; // No word to start from
         }
 
+        let start = self.policy.search_start(self, *hint);
+        let allocated = self.admit(1, || self.find_bit(start.index, start.offset, start.wrap));
+        *hint = self.policy.hint_after_get(*hint, allocated, 1, self.depth);
+
         allocated
     }
 
@@ -424,11 +518,26 @@ impl Sbitmap {
 
         // Clear the bit atomically with release semantics
         self.clear_bit(offset, &self.map[index].word);
+        self.freeze.wake_waiters();
 
-        // Update hint for better cache locality (non-round-robin mode)
-        if !self.round_robin && bitnr < self.depth {
-            *hint = bitnr;
-        }
+        *hint = self.policy.hint_after_put(*hint, bitnr);
+    }
+
+    /// Allocate the lowest free bit
+    ///
+    /// Words are searched from the first one and each word from bit 0, so
+    /// allocated bits stay packed at the bottom of the map. Under
+    /// concurrency the result is the lowest bit that was free when its word
+    /// was examined; a bit freed behind the search is not seen. All callers
+    /// contend on the lowest words, so this scales worse than `get()`.
+    ///
+    /// This operation provides acquire barrier semantics on success.
+    ///
+    /// # Returns
+    /// * `Some(bit_number)` - Successfully allocated bit number
+    /// * `None` - No free bits available
+    pub fn get_lowest(&self) -> Option<usize> {
+        self.admit(1, || self.find_bit(0, 0, false))
     }
 
     /// Allocate nr_bits consecutive free bits from the bitmap
@@ -459,38 +568,18 @@ impl Sbitmap {
             *hint = 0;
         }
 
-        let h = *hint;
-        let index = self.bit_to_index(h);
-
-        // Calculate bit offset within the word
-        let alloc_hint = if self.round_robin {
-            self.bit_to_offset(h)
-        } else {
-            0
-        };
-
-        let allocated = self.find_batch(index, alloc_hint, nr_bits, !self.round_robin);
-
-        // Update hint based on allocation result
-        match allocated {
-            None => {
-                // Map is full, reset hint to 0
-                *hint = 0;
-            }
-            Some(nr) if nr == h || self.round_robin => {
-                // Only update if we used the hint or in round-robin mode
-                let next_hint = nr + nr_bits;
-                *hint = if next_hint >= self.depth {
-                    0
-                } else {
-                    next_hint
-                };
-            }
-            _ => {
-                // Don't update hint if we didn't use it
-            }
+        if self.depth == 0 {
+            return None; // No word to start from
         }
 
+        let start = self.policy.search_start(self, *hint);
+        let allocated = self.admit(nr_bits, || {
+            self.find_batch(start.index, start.offset, nr_bits, start.wrap)
+        });
+        *hint = self
+            .policy
+            .hint_after_get(*hint, allocated, nr_bits, self.depth);
+
         allocated
     }
 
@@ -536,12 +625,10 @@ impl Sbitmap {
 
         self.map[start_index]
             .word
-            .fetch_and(clear_mask, Ordering::Release);
+            .fetch_and(clear_mask, Ordering::SeqCst);
+        self.freeze.wake_waiters();
 
-        // Update hint for better cache locality (non-round-robin mode)
-        if !self.round_robin && bitnr < self.depth {
-            *hint = bitnr;
-        }
+        *hint = self.policy.hint_after_put(*hint, bitnr);
     }
 
     /// Get the total number of bits in the bitmap
@@ -549,6 +636,11 @@ impl Sbitmap {
         self.depth
     }
 
+    /// Get the allocation policy
+    pub fn policy(&self) -> AllocPolicy {
+        self.policy
+    }
+
     /// Get the number of bits per word
     pub fn bits_per_word(&self) -> usize {
         1usize << self.shift
@@ -564,7 +656,28 @@ impl Sbitmap {
         let offset = self.bit_to_offset(bitnr);
         let word = self.map[index].word.load(Ordering::Relaxed);
 
-        (word & (1usize << offset)) != 0
+        (word & (W::Bits::ONE << offset)) != W::Bits::ZERO
+    }
+
+    /// Find the highest allocated bit
+    ///
+    /// Together with `get_lowest()` this tells how far a packed table is in
+    /// use, e.g. to shrink it. Words are read from the top without a common
+    /// snapshot, so the result may be stale under concurrent use.
+    ///
+    /// # Returns
+    /// * `Some(bit_number)` - Highest allocated bit number
+    /// * `None` - No bits are allocated
+    pub fn highest_set_bit(&self) -> Option<usize> {
+        (0..self.map_nr).rev().find_map(|i| {
+            let word = self.map[i].word.load(Ordering::Relaxed);
+            let word = word & Self::make_mask(self.map_depth(i));
+            if word == W::Bits::ZERO {
+                None
+            } else {
+                Some((i << self.shift) + W::Bits::BITS - 1 - word.leading_zeros())
+            }
+        })
     }
 
     /// Count the number of allocated (set) bits
@@ -572,18 +685,39 @@ impl Sbitmap {
         let mut count = 0;
         for i in 0..self.map_nr {
             let word = self.map[i].word.load(Ordering::Relaxed);
-            let depth = self.map_depth(i);
-            let mask = if depth == BITS_PER_WORD {
-                usize::MAX
-            } else {
-                (1usize << depth) - 1
-            };
-            count += (word & mask).count_ones() as usize;
+            count += (word & Self::make_mask(self.map_depth(i))).count_ones();
         }
         count
     }
 }
 
+/// Word-level helpers exposed to the fuzz targets in fuzz/
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub mod fuzz {
+    use super::Sbitmap;
+
+    /// Bits per word on this platform
+    pub const BITS_PER_WORD: usize = super::BITS_PER_WORD;
+
+    pub fn find_next_zero_bit(word: usize, depth: usize, hint: usize) -> Option<usize> {
+        <Sbitmap>::find_next_zero_bit(word, depth, hint)
+    }
+
+    pub fn find_next_zero_batch(
+        word: usize,
+        depth: usize,
+        hint: usize,
+        nr_bits: usize,
+    ) -> Option<usize> {
+        <Sbitmap>::find_next_zero_batch(word, depth, hint, nr_bits)
+    }
+
+    pub fn make_mask(nr_bits: usize) -> usize {
+        <Sbitmap>::make_mask(nr_bits)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -765,6 +899,37 @@ mod tests {
         assert_eq!(sb3.bits_per_word(), 16); // 2^4 = 16
     }
 
+    #[test]
+    fn test_get_lowest_packs() {
+        let sb = Sbitmap::new(200, Some(3), false);
+        assert_eq!(sb.highest_set_bit(), None);
+
+        let bits: Vec<usize> = (0..50).map(|_| sb.get_lowest().unwrap()).collect();
+        assert_eq!(bits, (0..50).collect::<Vec<_>>());
+        assert_eq!(sb.highest_set_bit(), Some(49));
+
+        // Holes are refilled lowest first, whatever the caller's hint
+        let mut hint = 0;
+        for bit in [3, 17, 40] {
+            sb.put(bit, &mut hint);
+        }
+        assert_eq!(sb.get_lowest(), Some(3));
+        assert_eq!(sb.get_lowest(), Some(17));
+
+        // Shrinking from the top lowers the highest set bit
+        sb.put(49, &mut hint);
+        sb.put(48, &mut hint);
+        assert_eq!(sb.highest_set_bit(), Some(47));
+        assert_eq!(sb.get_lowest(), Some(40));
+        assert_eq!(sb.get_lowest(), Some(48));
+
+        // Up to the last bit of a partial word
+        let sb = Sbitmap::new(70, None, false);
+        let all: Vec<usize> = std::iter::from_fn(|| sb.get_lowest()).collect();
+        assert_eq!(all, (0..70).collect::<Vec<_>>());
+        assert_eq!(sb.highest_set_bit(), Some(69));
+    }
+
     #[test]
     fn test_round_robin() {
         // Test that round-robin mode allocates bits in sequential order
@@ -924,6 +1089,134 @@ mod tests {
         assert_eq!(sb.weight(), 0);
     }
 
+    #[test]
+    fn test_find_next_zero_bit_edges() {
+        // hint == depth finds nothing, including a full-width word
+        assert_eq!(<Sbitmap>::find_next_zero_bit(0, 8, 8), None);
+        assert_eq!(
+            <Sbitmap>::find_next_zero_bit(0, BITS_PER_WORD, BITS_PER_WORD),
+            None
+        );
+
+        // The last bit of a full-width word is reachable
+        let word = usize::MAX >> 1;
+        assert_eq!(
+            <Sbitmap>::find_next_zero_bit(word, BITS_PER_WORD, 0),
+            Some(BITS_PER_WORD - 1)
+        );
+        assert_eq!(
+            <Sbitmap>::find_next_zero_batch(0, BITS_PER_WORD, 0, BITS_PER_WORD),
+            Some(0)
+        );
+    }
+
+    /// Allocation, batches at word boundaries and snapshots with word type W
+    fn check_word_type<W: BitmapWord>() {
+        let bits = W::Bits::BITS;
+        let max_shift = bits.trailing_zeros();
+
+        // Explicit shifts beyond the word width are clamped
+        let sb = Sbitmap::<W>::with_word_type(4 * bits, Some(max_shift + 1), false);
+        assert_eq!(sb.bits_per_word(), bits);
+
+        // Every bit of a partial last word is reachable, and only those
+        let depth = 3 * bits + 5;
+        let sb = Sbitmap::<W>::with_word_type(depth, Some(max_shift), false);
+        let mut hint = 0;
+        let mut all: Vec<usize> = std::iter::from_fn(|| sb.get(&mut hint)).collect();
+        all.sort_unstable();
+        assert_eq!(all, (0..depth).collect::<Vec<_>>());
+        assert_eq!(sb.weight(), depth);
+
+        let snap = sb.snapshot();
+        let restored = Sbitmap::<W>::try_from(&snap).unwrap();
+        assert_eq!(restored.weight(), depth);
+        assert_eq!(restored.snapshot(), snap);
+
+        for bit in all {
+            sb.put(bit, &mut hint);
+        }
+        assert_eq!(sb.weight(), 0);
+
+        // Full-width batches fill whole words, and never the partial one
+        let mut starts = Vec::new();
+        while let Some(start) = sb.get_batch(bits, &mut hint) {
+            starts.push(start);
+        }
+        starts.sort_unstable();
+        assert_eq!(starts, vec![0, bits, 2 * bits]);
+        assert_eq!(sb.get_batch(6, &mut hint), None);
+        assert_eq!(sb.get_batch(5, &mut hint), Some(3 * bits));
+        sb.put_batch(bits, bits, &mut hint);
+        assert_eq!(sb.weight(), 2 * bits + 5);
+        assert!(!sb.test_bit(bits) && !sb.test_bit(2 * bits - 1));
+        assert!(sb.test_bit(2 * bits));
+    }
+
+    #[test]
+    fn test_word_types() {
+        check_word_type::<std::sync::atomic::AtomicU32>();
+        check_word_type::<std::sync::atomic::AtomicU64>();
+        check_word_type::<AtomicUsize>();
+        #[cfg(feature = "atomic128")]
+        check_word_type::<portable_atomic::AtomicU128>();
+    }
+
+    #[test]
+    fn test_word_type_snapshot_portability() {
+        use std::sync::atomic::{AtomicU32, AtomicU64};
+
+        // A 32-bit word layout restores into 64-bit words
+        let narrow = Sbitmap::<AtomicU32>::with_word_type(100, None, false);
+        let mut hint = 0;
+        let bit = narrow.get(&mut hint).unwrap();
+        let wide = Sbitmap::<AtomicU64>::try_from(&narrow.snapshot()).unwrap();
+        assert_eq!(wide.bits_per_word(), narrow.bits_per_word());
+        assert!(wide.test_bit(bit));
+
+        // But 64-bit words don't fit into 32-bit ones
+        let wide = Sbitmap::<AtomicU64>::with_word_type(256, Some(6), false);
+        assert!(matches!(
+            Sbitmap::<AtomicU32>::try_from(&wide.snapshot()),
+            Err(SnapshotError::InvalidShift(6))
+        ));
+    }
+
+    #[test]
+    fn test_find_next_zero_batch_matches_naive() {
+        // The first start in [hint, depth - nr_bits] whose nr_bits are clear
+        fn naive(word: usize, depth: usize, hint: usize, nr_bits: usize) -> Option<usize> {
+            (hint..depth)
+                .filter(|&start| start + nr_bits <= depth)
+                .find(|&start| (start..start + nr_bits).all(|bit| word & (1 << bit) == 0))
+        }
+
+        // Random words plus sparse and dense ones with long runs
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut words = vec![0, usize::MAX, 0x8000_0001, usize::MAX >> 3];
+        for _ in 0..64 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let r = state as usize;
+            words.extend([r, r & (r >> 1), r | (r << 3)]);
+        }
+
+        for word in words {
+            for depth in [1, 7, 31, BITS_PER_WORD - 1, BITS_PER_WORD] {
+                for nr_bits in 1..=depth {
+                    for hint in (0..=depth).step_by(3) {
+                        assert_eq!(
+                            <Sbitmap>::find_next_zero_batch(word, depth, hint, nr_bits),
+                            naive(word, depth, hint, nr_bits),
+                            "word={word:#x} depth={depth} hint={hint} nr_bits={nr_bits}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_batch_basic() {
         let sb = Sbitmap::new(64, None, false);
@@ -953,12 +1246,13 @@ mod tests {
     fn test_batch_multiple_allocations() {
         let sb = Sbitmap::new(128, None, false);
         let mut hint = 0;
-        let mut batches = Vec::new();
 
         // Allocate multiple batches of different sizes
-        batches.push(sb.get_batch(3, &mut hint).expect("Should allocate 3 bits"));
-        batches.push(sb.get_batch(5, &mut hint).expect("Should allocate 5 bits"));
-        batches.push(sb.get_batch(2, &mut hint).expect("Should allocate 2 bits"));
+        let batches = [
+            sb.get_batch(3, &mut hint).expect("Should allocate 3 bits"),
+            sb.get_batch(5, &mut hint).expect("Should allocate 5 bits"),
+            sb.get_batch(2, &mut hint).expect("Should allocate 2 bits"),
+        ];
 
         assert_eq!(sb.weight(), 3 + 5 + 2);
 
@@ -1152,6 +1446,39 @@ mod tests {
         assert_eq!(sb.weight(), 0);
     }
 
+    #[test]
+    fn test_batch_concurrent_churn() {
+        // Regression test: a batch allocation that loses a race must not
+        // leave behind bits it set but does not own
+        let sb = Arc::new(Sbitmap::new(16, Some(3), false));
+        let mut handles = vec![];
+
+        for t in 0..8 {
+            let sb_clone = Arc::clone(&sb);
+            let handle = thread::spawn(move || {
+                let mut hint = 0;
+
+                for i in 0..100_000 {
+                    let size = 1 + (i + t) % 4;
+                    if let Some(start) = sb_clone.get_batch(size, &mut hint) {
+                        for bit in start..start + size {
+                            assert!(sb_clone.test_bit(bit));
+                        }
+                        sb_clone.put_batch(start, size, &mut hint);
+                    }
+                }
+            });
+            handles.push(handle);
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // Every allocated bit was freed, so nothing may be left set
+        assert_eq!(sb.weight(), 0);
+    }
+
     #[test]
     fn test_batch_fragmentation() {
         // Test that batch allocation works correctly with fragmented bitmaps
@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Minimal NUMA topology and CPU affinity helpers
+//
+// Only what `ShardedSbitmap` needs: the CPUs of each online node, the CPU
+// the caller runs on, and running a closure on a given set of CPUs so the
+// memory it touches first is placed on their node. Everything degrades to
+// a single node without affinity on other platforms.
+
+/// Parse a sysfs CPU list such as "0-3,8,10-11"
+///
+/// Returns None if any part of the list is malformed.
+pub(crate) fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((lo, hi)) => {
+                let lo: usize = lo.trim().parse().ok()?;
+                let hi: usize = hi.trim().parse().ok()?;
+                if lo > hi {
+                    return None;
+                }
+                cpus.extend(lo..=hi);
+            }
+            None => cpus.push(part.trim().parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// CPUs of each online NUMA node, in node order
+///
+/// Nodes without CPUs (memory-only nodes) are skipped. Returns an empty
+/// list if the topology cannot be read.
+#[cfg(target_os = "linux")]
+pub(crate) fn online_nodes() -> Vec<Vec<usize>> {
+    let mut nodes: Vec<(usize, Vec<usize>)> = Vec::new();
+    let entries = match std::fs::read_dir("/sys/devices/system/node") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let id = match name
+            .to_str()
+            .and_then(|n| n.strip_prefix("node"))
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            Some(id) => id,
+            None => continue,
+        };
+        let cpus = std::fs::read_to_string(entry.path().join("cpulist"))
+            .ok()
+            .and_then(|list| parse_cpu_list(&list));
+        if let Some(cpus) = cpus.filter(|c| !c.is_empty()) {
+            nodes.push((id, cpus));
+        }
+    }
+
+    nodes.sort_unstable_by_key(|&(id, _)| id);
+    nodes.into_iter().map(|(_, cpus)| cpus).collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn online_nodes() -> Vec<Vec<usize>> {
+    Vec::new()
+}
+
+/// CPU the calling thread is currently running on
+#[cfg(target_os = "linux")]
+#[inline]
+pub(crate) fn current_cpu() -> Option<usize> {
+    // SAFETY: sched_getcpu() has no preconditions
+    let cpu = unsafe { libc::sched_getcpu() };
+    usize::try_from(cpu).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+#[inline]
+pub(crate) fn current_cpu() -> Option<usize> {
+    None
+}
+
+/// Restrict the calling thread to `cpus`, ignoring failures
+#[cfg(target_os = "linux")]
+fn bind_current_thread(cpus: &[usize]) {
+    // SAFETY: cpu_set_t is a plain bitmask for which all zeroes is the
+    // empty set, CPU_SET is only called with in-range CPU numbers, and
+    // sched_setaffinity() only reads the set
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        let max = std::mem::size_of::<libc::cpu_set_t>() * 8;
+        for &cpu in cpus.iter().filter(|&&c| c < max) {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            log::debug!("numa: failed to bind thread to CPUs {cpus:?}");
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_current_thread(_cpus: &[usize]) {}
+
+/// Run `f` on a thread bound to `cpus` and return its result
+///
+/// With the default first-touch policy, memory that `f` allocates and
+/// writes ends up on the NUMA node of those CPUs. If `cpus` is empty, `f`
+/// runs on the calling thread.
+pub(crate) fn run_on_cpus<T, F>(cpus: &[usize], f: F) -> T
+where
+    T: Send,
+    F: FnOnce() -> T + Send,
+{
+    if cpus.is_empty() {
+        return f();
+    }
+
+    std::thread::scope(|scope| {
+        let handle = scope.spawn(|| {
+            bind_current_thread(cpus);
+            f()
+        });
+        match handle.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11\n"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_cpu_list("5"), Some(vec![5]));
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+        assert_eq!(parse_cpu_list("3-1"), None);
+        assert_eq!(parse_cpu_list("a"), None);
+    }
+
+    #[test]
+    fn test_run_on_cpus() {
+        assert_eq!(run_on_cpus(&[], || 1), 1);
+
+        // Bind to CPUs the thread may already run on; the result comes back
+        // whether or not binding succeeds
+        let cpus: Vec<usize> = online_nodes().into_iter().flatten().collect();
+        assert_eq!(run_on_cpus(&cpus, || vec![7u8; 16]), vec![7u8; 16]);
+    }
+}
@@ -0,0 +1,253 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Allocation policies
+//
+// A policy decides where `get()` and `get_batch()` start searching and how
+// the caller's hint moves after an allocation or a free. The bitmap methods
+// only ask the policy, so a new policy is added here and nowhere else.
+
+use crate::numa;
+use crate::random;
+use crate::{BitmapWord, Sbitmap};
+
+/// Order in which an sbitmap hands out free bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AllocPolicy {
+    /// Search from the start of the hint's word (the kernel's default)
+    ///
+    /// The hint advances past a bit allocated at it and moves to a freed
+    /// bit, so each task keeps reusing the same, warm words.
+    #[default]
+    Hint,
+    /// Hand out bits in strict order, continuing after the last one
+    ///
+    /// The hint always advances past the allocated bit and does not follow
+    /// freed bits, so a freed bit is reused only after the rest of the map.
+    RoundRobin,
+    /// Always take the lowest free bit, as `get_lowest()` does
+    ///
+    /// The hint is not used. All callers contend on the lowest words.
+    Lowest,
+    /// Start at a random bit, as `get_random()` does
+    ///
+    /// Uses a per-thread `SplitMix64` seeded from entropy. The hint is not
+    /// used for the search but advances past the allocated bit.
+    Random,
+    /// Start at a word chosen by the CPU the caller runs on
+    ///
+    /// Callers on the same CPU share a word and callers on different CPUs
+    /// spread over the map, whatever their hints. Falls back to `Hint` when
+    /// the CPU cannot be determined.
+    ///
+    /// Calls `sched_getcpu()` on every `get()` and `get_batch()`; it is
+    /// cheap on Linux (vDSO), but not free.
+    CpuAffine,
+}
+
+/// Where a search for free bits begins
+pub(crate) struct SearchStart {
+    /// Index of the first word to search
+    pub(crate) index: usize,
+    /// Bit offset within that word
+    pub(crate) offset: usize,
+    /// Whether to retry the first word from bit 0 after reaching its end
+    pub(crate) wrap: bool,
+}
+
+impl AllocPolicy {
+    /// Every policy, in the order of their codes
+    const ALL: [Self; 5] = [
+        Self::Hint,
+        Self::RoundRobin,
+        Self::Lowest,
+        Self::Random,
+        Self::CpuAffine,
+    ];
+
+    /// Stable code of the policy, as stored in snapshots
+    ///
+    /// `Hint` and `RoundRobin` are 0 and 1, the values of the round-robin
+    /// flag they replace. New policies take the next free code.
+    pub(crate) fn code(self) -> u8 {
+        match self {
+            Self::Hint => 0,
+            Self::RoundRobin => 1,
+            Self::Lowest => 2,
+            Self::Random => 3,
+            Self::CpuAffine => 4,
+        }
+    }
+
+    /// Policy with the given code, if this build knows it
+    pub(crate) fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|policy| policy.code() == code)
+    }
+
+    /// Where to start searching for a caller holding `hint`
+    ///
+    /// `hint` is a valid bit number of `sb`, whose depth is not zero.
+    pub(crate) fn search_start<W: BitmapWord>(self, sb: &Sbitmap<W>, hint: usize) -> SearchStart {
+        let from_bit = |bitnr: usize, wrap: bool| SearchStart {
+            index: sb.bit_to_index(bitnr),
+            offset: sb.bit_to_offset(bitnr),
+            wrap,
+        };
+
+        match self {
+            Self::Hint => SearchStart {
+                index: sb.bit_to_index(hint),
+                offset: 0,
+                wrap: true,
+            },
+            Self::RoundRobin => from_bit(hint, false),
+            Self::Lowest => from_bit(0, false),
+            Self::Random => from_bit(random::thread_random_below(sb.depth), true),
+            Self::CpuAffine => match numa::current_cpu() {
+                Some(cpu) => SearchStart {
+                    index: cpu % sb.map_nr,
+                    offset: 0,
+                    wrap: true,
+                },
+                None => Self::Hint.search_start(sb, hint),
+            },
+        }
+    }
+
+    /// The hint after allocating `nr_bits` bits starting at `allocated`
+    ///
+    /// # Arguments
+    /// * `hint` - The (sanitized) hint the search was started with
+    /// * `allocated` - Result of the allocation
+    /// * `nr_bits` - Number of bits allocated
+    /// * `depth` - Depth of the bitmap
+    pub(crate) fn hint_after_get(
+        self,
+        hint: usize,
+        allocated: Option<usize>,
+        nr_bits: usize,
+        depth: usize,
+    ) -> usize {
+        let Some(nr) = allocated else {
+            // Map is full, reset hint to 0
+            return 0;
+        };
+
+        let advance = match self {
+            // Only update if we used the hint
+            Self::Hint => nr == hint,
+            Self::RoundRobin | Self::Random | Self::CpuAffine => true,
+            Self::Lowest => false,
+        };
+
+        if !advance {
+            hint
+        } else if nr + nr_bits >= depth {
+            0
+        } else {
+            nr + nr_bits
+        }
+    }
+
+    /// The hint after freeing bit `bitnr`
+    pub(crate) fn hint_after_put(self, hint: usize, bitnr: usize) -> usize {
+        match self {
+            // Reuse the freed bit for better cache locality
+            Self::Hint => bitnr,
+            Self::RoundRobin | Self::Lowest | Self::Random | Self::CpuAffine => hint,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(sb: &Sbitmap, hint: &mut usize) -> Vec<usize> {
+        std::iter::from_fn(|| sb.get(hint)).collect()
+    }
+
+    #[test]
+    fn test_policy_from_round_robin() {
+        assert_eq!(Sbitmap::new(8, None, false).policy(), AllocPolicy::Hint);
+        assert_eq!(
+            Sbitmap::new(8, None, true).policy(),
+            AllocPolicy::RoundRobin
+        );
+        assert_eq!(AllocPolicy::default(), AllocPolicy::Hint);
+    }
+
+    #[test]
+    fn test_policy_codes() {
+        for policy in AllocPolicy::ALL {
+            assert_eq!(AllocPolicy::from_code(policy.code()), Some(policy));
+        }
+        assert_eq!(AllocPolicy::from_code(5), None);
+    }
+
+    #[test]
+    fn test_lowest_policy() {
+        let sb = Sbitmap::with_policy(64, Some(3), AllocPolicy::Lowest);
+        let mut hint = 40;
+
+        assert_eq!(drain(&sb, &mut hint), (0..64).collect::<Vec<_>>());
+
+        for bit in [50, 9, 30] {
+            sb.put(bit, &mut hint);
+        }
+        assert_eq!(hint, 0);
+        assert_eq!(sb.get(&mut hint), Some(9));
+        assert_eq!(sb.get_batch(2, &mut hint), None);
+        sb.put(31, &mut hint);
+        assert_eq!(sb.get_batch(2, &mut hint), Some(30));
+    }
+
+    #[test]
+    fn test_random_policy() {
+        let sb = Sbitmap::with_policy(1024, None, AllocPolicy::Random);
+        let mut hint = 0;
+
+        let bits: Vec<usize> = (0..16).map(|_| sb.get(&mut hint).unwrap()).collect();
+        assert_ne!(bits, (0..16).collect::<Vec<_>>());
+
+        // Every bit is still reachable
+        let mut all = bits;
+        all.extend(drain(&sb, &mut hint));
+        all.sort_unstable();
+        assert_eq!(all, (0..1024).collect::<Vec<_>>());
+
+        sb.put_batch(8, 4, &mut hint);
+        assert_eq!(sb.get_batch(4, &mut hint), Some(8));
+    }
+
+    #[test]
+    fn test_cpu_affine_policy() {
+        let sb = Sbitmap::with_policy(128, Some(4), AllocPolicy::CpuAffine);
+        let cpus: Vec<usize> = numa::online_nodes().into_iter().flatten().take(1).collect();
+
+        // Callers on one CPU fill its word first, whatever their hint.
+        // Binding may not take (e.g. in a restricted cpuset), so compare
+        // against the CPU that actually ran the allocations.
+        let (before, first, second, after) = numa::run_on_cpus(&cpus, || {
+            let mut hint = 0;
+            let mut other_hint = 100;
+            let before = numa::current_cpu();
+            let first = sb.get(&mut hint).unwrap();
+            let second = sb.get(&mut other_hint).unwrap();
+            (before, first, second, numa::current_cpu())
+        });
+        if let (Some(cpu), true) = (before, before == after) {
+            assert_eq!(first / 16, cpu % 8);
+            assert_eq!(second / 16, cpu % 8);
+        }
+
+        let mut hint = 0;
+        let mut all = vec![first, second];
+        all.extend(drain(&sb, &mut hint));
+        all.sort_unstable();
+        assert_eq!(all, (0..128).collect::<Vec<_>>());
+
+        sb.put(77, &mut hint);
+        assert_eq!(sb.get(&mut hint), Some(77));
+    }
+}
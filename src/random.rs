@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Randomized allocation for hard-to-guess bit numbers
+//
+// `get()` hands out low bits near the caller's hint, so the next ID is
+// easy to predict. `get_random()` starts the search at a random bit chosen
+// by a pluggable `RandomSource` instead, for connection or session IDs.
+
+use crate::{BitmapWord, Sbitmap};
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+thread_local! {
+    /// Generator behind `AllocPolicy::Random`, one per thread
+    static THREAD_RNG: RefCell<SplitMix64> = RefCell::new(SplitMix64::from_entropy());
+}
+
+/// Map a random value onto `0..n`
+///
+/// Multiply-shift avoids a division and has negligible bias.
+#[inline]
+fn below<R: RandomSource + ?Sized>(rng: &mut R, n: usize) -> usize {
+    ((rng.next_u64() as u128 * n as u128) >> 64) as usize
+}
+
+/// Random number in `0..n` from the calling thread's generator
+pub(crate) fn thread_random_below(n: usize) -> usize {
+    THREAD_RNG.with(|rng| below(&mut *rng.borrow_mut(), n))
+}
+
+/// Source of random numbers for `Sbitmap::get_random()`
+///
+/// Implement this for a cryptographically secure generator when IDs must
+/// resist an attacker who sees many of them; `SplitMix64` is fast but its
+/// output can be predicted from a few observed values.
+pub trait RandomSource {
+    /// Return the next 64 random bits
+    fn next_u64(&mut self) -> u64;
+}
+
+impl<R: RandomSource + ?Sized> RandomSource for &mut R {
+    #[inline]
+    fn next_u64(&mut self) -> u64 {
+        (**self).next_u64()
+    }
+}
+
+/// Small, fast, non-cryptographic generator (SplitMix64)
+///
+/// Good enough to make IDs non-sequential. Each thread should own its own
+/// instance, like its allocation hint.
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    /// Create a generator with a fixed seed, for reproducible sequences
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Create a generator seeded from the per-process random keys of the
+    /// standard library's `RandomState`
+    pub fn from_entropy() -> Self {
+        Self::new(RandomState::new().build_hasher().finish())
+    }
+}
+
+impl RandomSource for SplitMix64 {
+    #[inline]
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl<W: BitmapWord> Sbitmap<W> {
+    /// Allocate a free bit, starting the search at a random position
+    ///
+    /// A random bit number is drawn from `rng`, and the search starts at its
+    /// word and offset: first the rest of that word, then the start of it,
+    /// then the following words as in `get()`. The result is unpredictable
+    /// without knowing the RNG state, but not uniform over the free bits: a
+    /// free bit that follows a long run of allocated ones is picked more
+    /// often.
+    ///
+    /// Cost: one `next_u64()` call on top of a `get()`. Random starts touch
+    /// a different cache line almost every time, so expect more misses than
+    /// with a per-task hint. The bitmap's allocation policy is ignored.
+    ///
+    /// This operation provides acquire barrier semantics on success.
+    ///
+    /// # Arguments
+    /// * `rng` - Random source choosing the start position
+    /// * `hint` - Mutable reference to caller's allocation hint; not used
+    ///   for the search, but updated like `get()` does so the two can be mixed
+    ///
+    /// # Returns
+    /// * `Some(bit_number)` - Successfully allocated bit number
+    /// * `None` - No free bits available
+    pub fn get_random<R: RandomSource + ?Sized>(
+        &self,
+        rng: &mut R,
+        hint: &mut usize,
+    ) -> Option<usize> {
+        if self.depth == 0 {
+            *hint = 0;
+            return None;
+        }
+
+        let start = below(rng, self.depth);
+
+        let allocated = self.admit(1, || {
+            self.find_bit(self.bit_to_index(start), self.bit_to_offset(start), true)
+        });
+        *hint = match allocated {
+            Some(nr) if nr + 1 < self.depth => nr + 1,
+            _ => 0,
+        };
+
+        allocated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splitmix64_sequence() {
+        // Reference values of SplitMix64 seeded with 0
+        let mut rng = SplitMix64::new(0);
+        assert_eq!(rng.next_u64(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(rng.next_u64(), 0x6e78_9e6a_a1b9_65f4);
+
+        let a = SplitMix64::from_entropy().next_u64();
+        let b = SplitMix64::from_entropy().next_u64();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_get_random_spreads() {
+        let sb = Sbitmap::new(1024, None, false);
+        let mut rng = SplitMix64::new(42);
+        let mut hint = 0;
+
+        let bits: Vec<usize> = (0..16)
+            .map(|_| sb.get_random(&mut rng, &mut hint).unwrap())
+            .collect();
+
+        // Not the sequential prefix get() would return
+        assert_ne!(bits, (0..16).collect::<Vec<_>>());
+        let max = *bits.iter().max().unwrap();
+        assert!(max > 256, "random bits stay low: {bits:?}");
+        assert_eq!(sb.weight(), 16);
+    }
+
+    #[test]
+    fn test_get_random_fallback_scan() {
+        let sb = Sbitmap::new(200, Some(3), false);
+        let mut rng = SplitMix64::new(7);
+        let mut hint = 0;
+
+        // Every bit is reachable whatever the random start
+        let mut bits: Vec<usize> =
+            std::iter::from_fn(|| sb.get_random(&mut rng, &mut hint)).collect();
+        bits.sort_unstable();
+        assert_eq!(bits, (0..200).collect::<Vec<_>>());
+        assert_eq!(hint, 0);
+
+        // With one free bit left, any start finds it
+        for bit in [0, 77, 199] {
+            sb.put(bit, &mut hint);
+            assert_eq!(sb.get_random(&mut rng, &mut hint), Some(bit));
+        }
+
+        let empty = Sbitmap::new(0, None, false);
+        assert_eq!(empty.get_random(&mut rng, &mut hint), None);
+    }
+}
@@ -0,0 +1,389 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// NUMA-aware sharded sbitmap
+//
+// A plain `Sbitmap` keeps all of its words in one allocation, placed on
+// whichever node ran `Sbitmap::new()`. `ShardedSbitmap` splits the bits
+// into one shard per NUMA node, allocates each shard on its node, and
+// allocates from the caller's local shard first.
+
+use crate::numa;
+use crate::{Sbitmap, TagAllocator};
+use std::ops::Range;
+
+/// One node's part of a sharded bitmap
+struct Shard {
+    /// First global bit number of this shard
+    offset: usize,
+    /// The shard's bits, numbered from 0
+    map: Sbitmap,
+}
+
+impl Shard {
+    /// Whether global bit number `bitnr` belongs to this shard
+    #[inline]
+    fn contains(&self, bitnr: usize) -> bool {
+        bitnr >= self.offset && bitnr - self.offset < self.map.depth()
+    }
+
+    /// Translate a global hint into a hint within this shard
+    ///
+    /// Hints pointing into another shard are folded into this one so that
+    /// callers keep spreading over different words. An empty shard has no
+    /// valid hint and gets 0.
+    #[inline]
+    fn local_hint(&self, hint: usize) -> usize {
+        if self.contains(hint) {
+            hint - self.offset
+        } else {
+            hint.checked_rem(self.map.depth()).unwrap_or(0)
+        }
+    }
+}
+
+/// Scalable bitmap with one shard per NUMA node
+///
+/// Bit numbers are global: shard `i` owns the contiguous range returned by
+/// `shard_range(i)`. `get()` allocates from the shard of the node the
+/// caller runs on and only steals from remote shards when the local one is
+/// full. Each shard is an `Sbitmap` built on a thread bound to its node's
+/// CPUs, so its words are first touched, and therefore placed, there.
+pub struct ShardedSbitmap {
+    /// Total number of bits across all shards
+    depth: usize,
+    /// Shards in node order
+    shards: Vec<Shard>,
+    /// Shard index for each CPU number
+    cpu_shard: Vec<usize>,
+}
+
+impl ShardedSbitmap {
+    /// Create a sharded bitmap with one shard per online NUMA node
+    ///
+    /// Falls back to a single shard if the topology cannot be read.
+    ///
+    /// # Arguments
+    /// * `depth` - Total number of bits
+    /// * `shift` - log2(bits per word) of each shard; auto-calculated from the shard depth if None
+    /// * `round_robin` - Enable strict round-robin allocation within each shard
+    pub fn new(depth: usize, shift: Option<u32>, round_robin: bool) -> Self {
+        Self::with_nodes(depth, shift, round_robin, &numa::online_nodes())
+    }
+
+    /// Create a sharded bitmap with one shard per entry of `nodes`
+    ///
+    /// Each entry lists the CPUs of one node. The shard is allocated on a
+    /// thread bound to those CPUs, and callers running on them use it as
+    /// their local shard; an empty list allocates on the calling thread.
+    /// Bits are split evenly, with earlier shards taking the remainder.
+    /// There are never more shards than bits, and always at least one.
+    ///
+    /// # Arguments
+    /// * `depth` - Total number of bits
+    /// * `shift` - log2(bits per word) of each shard; auto-calculated from the shard depth if None
+    /// * `round_robin` - Enable strict round-robin allocation within each shard
+    /// * `nodes` - CPUs of each node, one shard per entry
+    pub fn with_nodes(
+        depth: usize,
+        shift: Option<u32>,
+        round_robin: bool,
+        nodes: &[Vec<usize>],
+    ) -> Self {
+        let nr_shards = nodes.len().clamp(1, depth.max(1));
+        let base = depth / nr_shards;
+        let extra = depth % nr_shards;
+
+        let mut shards = Vec::with_capacity(nr_shards);
+        let mut offset = 0;
+        for i in 0..nr_shards {
+            let shard_depth = base + usize::from(i < extra);
+            let cpus = nodes.get(i).map_or(&[][..], |c| c.as_slice());
+            let map = numa::run_on_cpus(cpus, || Sbitmap::new(shard_depth, shift, round_robin));
+            shards.push(Shard { offset, map });
+            offset += shard_depth;
+        }
+
+        let max_cpu = nodes.iter().flatten().copied().max().map_or(0, |c| c + 1);
+        let mut cpu_shard: Vec<usize> = (0..max_cpu).map(|cpu| cpu % nr_shards).collect();
+        for (i, cpus) in nodes.iter().enumerate().take(nr_shards) {
+            for &cpu in cpus {
+                cpu_shard[cpu] = i;
+            }
+        }
+
+        log::debug!(
+            "sbitmap::sharded: depth={depth}, shards={nr_shards}, shard_depth={base}+{}",
+            usize::from(extra > 0)
+        );
+
+        Self {
+            depth,
+            shards,
+            cpu_shard,
+        }
+    }
+
+    /// Index of the shard local to the calling thread's CPU
+    pub fn local_shard(&self) -> usize {
+        match numa::current_cpu() {
+            Some(cpu) => self
+                .cpu_shard
+                .get(cpu)
+                .copied()
+                .unwrap_or(cpu % self.shards.len()),
+            None => 0,
+        }
+    }
+
+    /// Allocate a free bit, preferring the local shard
+    ///
+    /// This operation provides acquire barrier semantics on success.
+    ///
+    /// # Arguments
+    /// * `hint` - Mutable reference to caller's allocation hint (a global bit number)
+    ///
+    /// # Returns
+    /// * `Some(bit_number)` - Successfully allocated global bit number
+    /// * `None` - No free bits in any shard
+    pub fn get(&self, hint: &mut usize) -> Option<usize> {
+        self.get_from(self.local_shard(), hint)
+    }
+
+    /// Allocate a free bit, trying shard `local` first
+    ///
+    /// Only an allocation from `local` updates the hint, so it keeps
+    /// pointing into the local shard after a steal.
+    fn get_from(&self, local: usize, hint: &mut usize) -> Option<usize> {
+        let nr_shards = self.shards.len();
+        let shard = &self.shards[local];
+        let mut local_hint = shard.local_hint(*hint);
+        if let Some(bit) = shard.map.get(&mut local_hint) {
+            *hint = shard.offset + local_hint;
+            return Some(shard.offset + bit);
+        }
+
+        // Local shard is full, steal from the others in turn
+        for i in 1..nr_shards {
+            let shard = &self.shards[(local + i) % nr_shards];
+            let mut remote_hint = shard.local_hint(*hint);
+            if let Some(bit) = shard.map.get(&mut remote_hint) {
+                return Some(shard.offset + bit);
+            }
+        }
+
+        None
+    }
+
+    /// Free a previously allocated bit
+    ///
+    /// This operation provides release barrier semantics. The bit goes back
+    /// to the shard it came from, whichever node the caller runs on.
+    ///
+    /// # Arguments
+    /// * `bitnr` - The global bit number to free (must have been returned by get())
+    /// * `hint` - Mutable reference to caller's allocation hint
+    pub fn put(&self, bitnr: usize, hint: &mut usize) {
+        let Some(index) = self.shard_of(bitnr) else {
+            return; // Invalid bit number
+        };
+        let shard = &self.shards[index];
+        let same_shard = shard.contains(*hint);
+        let mut local_hint = shard.local_hint(*hint);
+
+        shard.map.put(bitnr - shard.offset, &mut local_hint);
+
+        // Only follow the freed bit if it is in the shard the hint is for
+        if same_shard {
+            *hint = shard.offset + local_hint;
+        }
+    }
+
+    /// Index of the shard owning global bit number `bitnr`
+    pub fn shard_of(&self, bitnr: usize) -> Option<usize> {
+        if bitnr >= self.depth {
+            return None;
+        }
+        Some(self.shards.partition_point(|s| s.offset <= bitnr) - 1)
+    }
+
+    /// Global bit numbers owned by shard `index`
+    ///
+    /// # Panics
+    /// Panics if `index >= nr_shards()`.
+    pub fn shard_range(&self, index: usize) -> Range<usize> {
+        let shard = &self.shards[index];
+        shard.offset..shard.offset + shard.map.depth()
+    }
+
+    /// Get the number of shards
+    pub fn nr_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Get the total number of bits across all shards
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Check if a specific global bit is set (allocated)
+    pub fn test_bit(&self, bitnr: usize) -> bool {
+        match self.shard_of(bitnr) {
+            Some(index) => {
+                let shard = &self.shards[index];
+                shard.map.test_bit(bitnr - shard.offset)
+            }
+            None => false,
+        }
+    }
+
+    /// Count the number of allocated (set) bits across all shards
+    pub fn weight(&self) -> usize {
+        self.shards.iter().map(|s| s.map.weight()).sum()
+    }
+}
+
+impl TagAllocator for ShardedSbitmap {
+    #[inline]
+    fn get(&self, hint: &mut usize) -> Option<usize> {
+        ShardedSbitmap::get(self, hint)
+    }
+
+    #[inline]
+    fn put(&self, tag: usize, hint: &mut usize) {
+        ShardedSbitmap::put(self, tag, hint)
+    }
+
+    #[inline]
+    fn depth(&self) -> usize {
+        ShardedSbitmap::depth(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three nodes with no CPUs: shards are allocated on the caller
+    fn three_nodes() -> Vec<Vec<usize>> {
+        vec![Vec::new(), Vec::new(), Vec::new()]
+    }
+
+    #[test]
+    fn test_shard_layout() {
+        let sb = ShardedSbitmap::with_nodes(10, None, false, &three_nodes());
+        assert_eq!(sb.nr_shards(), 3);
+        assert_eq!(sb.depth(), 10);
+        assert_eq!(sb.shard_range(0), 0..4);
+        assert_eq!(sb.shard_range(1), 4..7);
+        assert_eq!(sb.shard_range(2), 7..10);
+        assert_eq!(sb.shard_of(3), Some(0));
+        assert_eq!(sb.shard_of(4), Some(1));
+        assert_eq!(sb.shard_of(9), Some(2));
+        assert_eq!(sb.shard_of(10), None);
+
+        // Never more shards than bits, never fewer than one
+        assert_eq!(
+            ShardedSbitmap::with_nodes(2, None, false, &three_nodes()).nr_shards(),
+            2
+        );
+        assert_eq!(
+            ShardedSbitmap::with_nodes(8, None, false, &[]).nr_shards(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_local_first_then_steal() {
+        let sb = ShardedSbitmap::with_nodes(12, None, false, &three_nodes());
+        let mut hint = 0;
+
+        // The local shard is used until it is full
+        for _ in 0..4 {
+            let bit = sb.get_from(1, &mut hint).unwrap();
+            assert!(sb.shard_range(1).contains(&bit));
+        }
+
+        // Then the other shards, starting with the next one
+        let bit = sb.get_from(1, &mut hint).unwrap();
+        assert!(sb.shard_range(2).contains(&bit));
+
+        let mut bits = vec![bit];
+        while let Some(bit) = sb.get_from(1, &mut hint) {
+            bits.push(bit);
+        }
+        assert_eq!(bits.len(), 8);
+        assert_eq!(sb.weight(), 12);
+
+        // Freed bits go back to their own shard
+        sb.put(9, &mut hint);
+        assert!(!sb.test_bit(9));
+        assert_eq!(sb.get_from(0, &mut hint), Some(9));
+    }
+
+    #[test]
+    fn test_global_bits_unique() {
+        let sb = ShardedSbitmap::with_nodes(100, Some(3), false, &three_nodes());
+        let mut hint = 0;
+
+        let mut bits: Vec<usize> = (0..3)
+            .flat_map(|local| {
+                let mut taken = Vec::new();
+                for _ in 0..40 {
+                    if let Some(bit) = sb.get_from(local, &mut hint) {
+                        taken.push(bit);
+                    }
+                }
+                taken
+            })
+            .collect();
+        bits.sort_unstable();
+        assert_eq!(bits, (0..100).collect::<Vec<_>>());
+        assert_eq!(sb.get(&mut hint), None);
+
+        for bit in bits {
+            sb.put(bit, &mut hint);
+        }
+        assert_eq!(sb.weight(), 0);
+    }
+
+    #[test]
+    fn test_detected_topology() {
+        let sb = ShardedSbitmap::new(64, None, false);
+        assert!(sb.nr_shards() >= 1);
+        assert!(sb.local_shard() < sb.nr_shards());
+
+        // Pin to one CPU so the local shard can't change under the test
+        let cpus: Vec<usize> = numa::online_nodes().into_iter().flatten().take(1).collect();
+        let (local, bit) = numa::run_on_cpus(&cpus, || {
+            let mut hint = 0;
+            (sb.local_shard(), sb.get(&mut hint).unwrap())
+        });
+        assert!(sb.test_bit(bit));
+        if !cpus.is_empty() {
+            assert!(sb.shard_range(local).contains(&bit));
+        }
+
+        let mut hint = 0;
+        sb.put(bit, &mut hint);
+        assert_eq!(sb.weight(), 0);
+    }
+
+    #[test]
+    fn test_zero_depth() {
+        for sb in [
+            ShardedSbitmap::new(0, None, false),
+            ShardedSbitmap::with_nodes(0, None, false, &three_nodes()),
+        ] {
+            assert_eq!(sb.depth(), 0);
+            assert_eq!(sb.nr_shards(), 1);
+
+            let mut hint = 5;
+            assert_eq!(sb.get(&mut hint), None);
+            assert_eq!(sb.get_from(0, &mut hint), None);
+            sb.put(0, &mut hint);
+            assert_eq!(sb.shard_of(0), None);
+            assert!(!sb.test_bit(0));
+            assert_eq!(sb.weight(), 0);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Tag-indexed object storage
+//
+// Most users of a tag allocate it and then store a request at
+// `table[tag]`. `TagSlab<T>` pairs an `Sbitmap` with one slot of `T` per
+// tag and hands out a `Key` that proves the slot is occupied, so reads
+// need no lock: the bitmap's acquire on `get()` and release on `put()`
+// order slot accesses between successive owners of a tag.
+
+use crate::Sbitmap;
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Source of unique slab identities, to reject keys from other slabs
+static NEXT_SLAB_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Proof of an occupied `TagSlab` slot
+///
+/// Returned by `insert()` and consumed by `remove()`. A key cannot be
+/// cloned, so while one exists its slot stays occupied and `get()` can
+/// hand out references without locking. Dropping a key without removing
+/// it leaks the slot until the slab is dropped.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    /// Tag of the slot
+    tag: usize,
+    /// Identity of the slab that issued the key
+    slab: usize,
+}
+
+impl Key {
+    /// Get the tag of the slot, e.g. to put in a command sent to hardware
+    pub fn tag(&self) -> usize {
+        self.tag
+    }
+}
+
+/// Fixed-size table of `T` indexed by tags of an `Sbitmap`
+///
+/// `insert()` allocates a tag with `Sbitmap::get()` and moves the value
+/// into its slot; `remove()` moves it out and frees the tag with
+/// `Sbitmap::put()`. A tag is only reused after its value has been moved
+/// out, and the next owner sees the slot only after the bitmap's
+/// acquire, so slot accesses never race.
+pub struct TagSlab<T> {
+    /// Allocation state of the slots; a set bit means occupied
+    map: Sbitmap,
+    /// One slot per tag, initialized iff its bit is set
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// Identity stamped into issued keys
+    id: usize,
+}
+
+// SAFETY: a slot is only written by the owner of its tag, and the bitmap
+// orders owners of a tag one after another. Values move between threads
+// (T: Send) and are shared through get() (T: Sync).
+unsafe impl<T: Send> Send for TagSlab<T> {}
+unsafe impl<T: Send + Sync> Sync for TagSlab<T> {}
+
+impl<T> TagSlab<T> {
+    /// Create a slab with one slot per bit of `map`
+    ///
+    /// # Arguments
+    /// * `map` - The bitmap to allocate tags from; its policy applies
+    ///
+    /// # Panics
+    /// Panics if `map` has allocated bits, since their slots hold no value.
+    pub fn new(map: Sbitmap) -> Self {
+        assert_eq!(map.weight(), 0, "TagSlab needs an empty bitmap");
+        let slots = (0..map.depth())
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect();
+
+        Self {
+            map,
+            slots,
+            id: NEXT_SLAB_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Slot of a key issued by this slab
+    ///
+    /// # Panics
+    /// Panics if the key comes from another slab.
+    #[inline]
+    fn slot(&self, key: &Key) -> &UnsafeCell<MaybeUninit<T>> {
+        assert_eq!(key.slab, self.id, "key from another TagSlab");
+        &self.slots[key.tag]
+    }
+
+    /// Store a value in a free slot
+    ///
+    /// # Arguments
+    /// * `value` - The value to store
+    /// * `hint` - Mutable reference to caller's allocation hint
+    ///
+    /// # Returns
+    /// * `Ok(key)` - Key of the slot now holding `value`
+    /// * `Err(value)` - Every slot is occupied; the value is handed back
+    pub fn insert(&self, value: T, hint: &mut usize) -> Result<Key, T> {
+        let Some(tag) = self.map.get(hint) else {
+            return Err(value);
+        };
+
+        // SAFETY: the tag was just allocated, so no key for it exists and
+        // nobody else accesses the slot; the acquire in get() ordered the
+        // previous owner's remove() before this write
+        unsafe { (*self.slots[tag].get()).write(value) };
+
+        Ok(Key { tag, slab: self.id })
+    }
+
+    /// Get a reference to the value of an occupied slot, without locking
+    ///
+    /// # Panics
+    /// Panics if the key comes from another slab.
+    pub fn get<'a>(&'a self, key: &'a Key) -> &'a T {
+        let slot = self.slot(key);
+        // SAFETY: the key proves the slot was initialized by insert(), and
+        // the slot cannot be removed while the key is borrowed
+        unsafe { (*slot.get()).assume_init_ref() }
+    }
+
+    /// Move the value out of a slot and free its tag
+    ///
+    /// # Arguments
+    /// * `key` - Key returned by insert()
+    /// * `hint` - Mutable reference to caller's allocation hint
+    ///
+    /// # Panics
+    /// Panics if the key comes from another slab.
+    pub fn remove(&self, key: Key, hint: &mut usize) -> T {
+        let slot = self.slot(&key);
+        // SAFETY: the key proves the slot is initialized and, being
+        // consumed, that no reference from get() is alive; the release in
+        // put() orders this read before the next owner's write
+        let value = unsafe { (*slot.get()).assume_init_read() };
+        self.map.put(key.tag, hint);
+        value
+    }
+
+    /// Count the occupied slots
+    pub fn len(&self) -> usize {
+        self.map.weight()
+    }
+
+    /// Check whether no slot is occupied
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the total number of slots
+    pub fn depth(&self) -> usize {
+        self.map.depth()
+    }
+}
+
+impl<T> Drop for TagSlab<T> {
+    fn drop(&mut self) {
+        for (tag, slot) in self.slots.iter_mut().enumerate() {
+            if self.map.test_bit(tag) {
+                // SAFETY: set bits mark initialized slots, and &mut self
+                // excludes any other access
+                unsafe { slot.get_mut().assume_init_drop() };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_insert_get_remove() {
+        let slab = TagSlab::new(Sbitmap::new(4, None, false));
+        let mut hint = 0;
+
+        let keys: Vec<Key> = (0..4)
+            .map(|i| slab.insert(format!("req{i}"), &mut hint).unwrap())
+            .collect();
+        assert_eq!(slab.len(), 4);
+        assert_eq!(
+            slab.insert("full".to_string(), &mut hint),
+            Err("full".to_string())
+        );
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(slab.get(key), &format!("req{i}"));
+        }
+
+        let mut keys = keys.into_iter();
+        let first = keys.next().unwrap();
+        let tag = first.tag();
+        assert_eq!(slab.remove(first, &mut hint), "req0");
+        assert_eq!(slab.len(), 3);
+
+        // The freed slot is reused
+        let key = slab.insert("again".to_string(), &mut hint).unwrap();
+        assert_eq!(key.tag(), tag);
+        assert_eq!(slab.get(&key), "again");
+    }
+
+    #[test]
+    fn test_drop_remaining_values() {
+        let value = Arc::new(());
+        {
+            let slab = TagSlab::new(Sbitmap::new(8, None, false));
+            let mut hint = 0;
+            // The key of the kept value is leaked, its value dropped with the slab
+            slab.insert(Arc::clone(&value), &mut hint).unwrap();
+            let removed = slab.insert(Arc::clone(&value), &mut hint).unwrap();
+            drop(slab.remove(removed, &mut hint));
+            assert_eq!(Arc::strong_count(&value), 2);
+        }
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "key from another TagSlab")]
+    fn test_foreign_key() {
+        let a = TagSlab::new(Sbitmap::new(8, None, false));
+        let b: TagSlab<u32> = TagSlab::new(Sbitmap::new(8, None, false));
+        let mut hint = 0;
+        let key = a.insert(1u32, &mut hint).unwrap();
+        b.get(&key);
+    }
+
+    #[test]
+    fn test_concurrent_slab() {
+        let slab = Arc::new(TagSlab::new(Sbitmap::new(64, None, false)));
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let slab = Arc::clone(&slab);
+                thread::spawn(move || {
+                    let mut hint = 0;
+                    for i in 0..2000usize {
+                        let value = (t, i);
+                        let key = slab.insert(value, &mut hint).unwrap();
+                        assert_eq!(*slab.get(&key), value);
+                        assert_eq!(slab.remove(key, &mut hint), value);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(slab.is_empty());
+    }
+}
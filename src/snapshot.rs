@@ -0,0 +1,560 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Snapshot and restore of sbitmap state
+//
+// A snapshot captures which bits are allocated so that the state can be
+// handed over to another process (e.g. across a live upgrade) and restored
+// with `Sbitmap::from_snapshot()`.
+
+use crate::{AllocPolicy, BitmapWord, Sbitmap, WordBits};
+use std::fmt;
+use std::sync::atomic::Ordering;
+
+/// Magic bytes at the start of the binary encoding
+const SNAPSHOT_MAGIC: [u8; 4] = *b"SBMP";
+
+/// Current version of the binary encoding
+const SNAPSHOT_VERSION: u8 = 1;
+
+/// Size of the fixed binary header: magic, version, policy, shift, depth
+const HEADER_LEN: usize = 4 + 1 + 1 + 1 + 8;
+
+/// Largest shift of any supported word type (128-bit words)
+const MAX_SHIFT: u32 = 7;
+
+/// Point-in-time copy of an sbitmap's configuration and allocated bits
+///
+/// Each entry of `words` holds the low `1 << shift` bits of the
+/// corresponding bitmap word, bit 0 of word 0 being bit number 0. Words of
+/// 128 bits (shift 7) take two consecutive entries, low half first.
+///
+/// Deserialized snapshots are checked like `from_bytes()` input, so every
+/// snapshot is consistent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawSnapshot"))]
+pub struct SbitmapSnapshot {
+    depth: usize,
+    shift: u32,
+    policy: AllocPolicy,
+    words: Vec<u64>,
+}
+
+/// Snapshot fields as deserialized, before validation
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawSnapshot {
+    depth: usize,
+    shift: u32,
+    policy: AllocPolicy,
+    words: Vec<u64>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawSnapshot> for SbitmapSnapshot {
+    type Error = SnapshotError;
+
+    fn try_from(raw: RawSnapshot) -> Result<Self, SnapshotError> {
+        let snapshot = Self {
+            depth: raw.depth,
+            shift: raw.shift,
+            policy: raw.policy,
+            words: raw.words,
+        };
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+}
+
+/// Error returned when a snapshot cannot be decoded or restored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Input does not start with the snapshot magic bytes
+    BadMagic,
+    /// Encoding version is not understood by this build
+    UnsupportedVersion(u8),
+    /// Input ended before the encoded bitmap did
+    Truncated,
+    /// Input has bytes left over after the encoded bitmap
+    TrailingBytes,
+    /// Depth does not fit in this platform's `usize`
+    InvalidDepth(u64),
+    /// Shift exceeds the word size of this platform
+    InvalidShift(u32),
+    /// Number of words does not match depth and shift
+    WordCountMismatch { expected: usize, found: usize },
+    /// A bit beyond the bitmap depth is set
+    BitOutOfRange(usize),
+    /// Allocation policy code is not understood by this build
+    UnknownPolicy(u8),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not an sbitmap snapshot (bad magic)"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {v}"),
+            Self::Truncated => write!(f, "snapshot data is truncated"),
+            Self::TrailingBytes => write!(f, "unexpected trailing bytes after snapshot"),
+            Self::InvalidDepth(d) => write!(f, "snapshot depth {d} is too large"),
+            Self::InvalidShift(s) => write!(f, "snapshot shift {s} exceeds word size"),
+            Self::WordCountMismatch { expected, found } => {
+                write!(f, "snapshot has {found} words, expected {expected}")
+            }
+            Self::BitOutOfRange(bit) => write!(f, "snapshot bit {bit} is beyond depth"),
+            Self::UnknownPolicy(p) => write!(f, "unknown snapshot allocation policy {p}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl SbitmapSnapshot {
+    /// Get the total number of bits in the snapshotted bitmap
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Get log2(bits per word) of the snapshotted bitmap
+    pub fn shift(&self) -> u32 {
+        self.shift
+    }
+
+    /// Check whether the snapshotted bitmap used round-robin allocation
+    pub fn round_robin(&self) -> bool {
+        self.policy == AllocPolicy::RoundRobin
+    }
+
+    /// Get the allocation policy of the snapshotted bitmap
+    pub fn policy(&self) -> AllocPolicy {
+        self.policy
+    }
+
+    /// Get the raw word contents
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Check if a specific bit was allocated when the snapshot was taken
+    pub fn test_bit(&self, bitnr: usize) -> bool {
+        if bitnr >= self.depth {
+            return false;
+        }
+
+        let (entry, bit) = self.locate(bitnr);
+        (self.words[entry] >> bit) & 1 != 0
+    }
+
+    /// Number of `words` entries per bitmap word
+    fn entries_per_word(shift: u32) -> usize {
+        (1usize << shift).div_ceil(u64::BITS as usize)
+    }
+
+    /// Check that the words match depth and shift
+    ///
+    /// Snapshots built from external input must pass this before any
+    /// other method is called.
+    fn validate(&self) -> Result<(), SnapshotError> {
+        if self.shift > MAX_SHIFT {
+            return Err(SnapshotError::InvalidShift(self.shift));
+        }
+
+        let bits_per_word = 1usize << self.shift;
+        let per_word = Self::entries_per_word(self.shift);
+        let expected = self.depth.div_ceil(bits_per_word) * per_word;
+        if self.words.len() != expected {
+            return Err(SnapshotError::WordCountMismatch {
+                expected,
+                found: self.words.len(),
+            });
+        }
+
+        for (i, entries) in self.words.chunks(per_word).enumerate() {
+            let depth = (self.depth - i * bits_per_word).min(bits_per_word);
+            for (k, &entry) in entries.iter().enumerate() {
+                // Bits of this entry that are within the word's depth
+                let valid = depth.saturating_sub(k * 64).min(64);
+                let mask = if valid == 64 {
+                    u64::MAX
+                } else {
+                    (1u64 << valid) - 1
+                };
+                let stray = entry & !mask;
+                if stray != 0 {
+                    let bit = i * bits_per_word + k * 64 + stray.trailing_zeros() as usize;
+                    return Err(SnapshotError::BitOutOfRange(bit));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Entry of `words` and bit within it holding bit number `bitnr`
+    fn locate(&self, bitnr: usize) -> (usize, usize) {
+        let offset = bitnr & ((1usize << self.shift) - 1);
+        let entry = (bitnr >> self.shift) * Self::entries_per_word(self.shift);
+        (entry + offset / 64, offset % 64)
+    }
+
+    /// Count the number of allocated bits in the snapshot
+    pub fn weight(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Encode the snapshot into a compact, versioned binary form
+    ///
+    /// The layout is a fixed header (magic `SBMP`, version, policy, shift and
+    /// little-endian u64 depth) followed by the allocated bits packed one
+    /// bit per tag, independent of the word layout.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.depth.div_ceil(8));
+
+        out.extend_from_slice(&SNAPSHOT_MAGIC);
+        out.push(SNAPSHOT_VERSION);
+        out.push(self.policy.code());
+        out.push(self.shift as u8);
+        out.extend_from_slice(&(self.depth as u64).to_le_bytes());
+
+        let mut byte = 0u8;
+        for bitnr in 0..self.depth {
+            if self.test_bit(bitnr) {
+                byte |= 1 << (bitnr % 8);
+            }
+            if bitnr % 8 == 7 {
+                out.push(byte);
+                byte = 0;
+            }
+        }
+        if self.depth % 8 != 0 {
+            out.push(byte);
+        }
+
+        out
+    }
+
+    /// Decode a snapshot produced by `to_bytes()`
+    ///
+    /// The decoded snapshot is validated against this platform's word size.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        if bytes.len() < SNAPSHOT_MAGIC.len() {
+            return Err(SnapshotError::Truncated);
+        }
+        if bytes[..4] != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err(SnapshotError::Truncated);
+        }
+
+        if bytes[4] != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(bytes[4]));
+        }
+        let policy =
+            AllocPolicy::from_code(bytes[5]).ok_or(SnapshotError::UnknownPolicy(bytes[5]))?;
+        let shift = bytes[6] as u32;
+        let raw_depth = u64::from_le_bytes(bytes[7..HEADER_LEN].try_into().unwrap());
+        let depth =
+            usize::try_from(raw_depth).map_err(|_| SnapshotError::InvalidDepth(raw_depth))?;
+        if shift > MAX_SHIFT {
+            return Err(SnapshotError::InvalidShift(shift));
+        }
+
+        let payload = &bytes[HEADER_LEN..];
+        let expected_len = depth.div_ceil(8);
+        if payload.len() < expected_len {
+            return Err(SnapshotError::Truncated);
+        }
+        if payload.len() > expected_len {
+            return Err(SnapshotError::TrailingBytes);
+        }
+
+        let nr_entries = depth.div_ceil(1usize << shift) * Self::entries_per_word(shift);
+        let mut snapshot = Self {
+            depth,
+            shift,
+            policy,
+            words: vec![0u64; nr_entries],
+        };
+        for bitnr in 0..depth {
+            if (payload[bitnr / 8] >> (bitnr % 8)) & 1 != 0 {
+                let (entry, bit) = snapshot.locate(bitnr);
+                snapshot.words[entry] |= 1u64 << bit;
+            }
+        }
+
+        // Bits past depth in the final byte must be clear
+        if depth % 8 != 0 && payload[depth / 8] >> (depth % 8) != 0 {
+            return Err(SnapshotError::BitOutOfRange(depth));
+        }
+
+        Ok(snapshot)
+    }
+}
+
+impl<W: BitmapWord> Sbitmap<W> {
+    /// Take a snapshot of the bitmap configuration and allocated bits
+    ///
+    /// Each word is read atomically with acquire semantics, but words are
+    /// not read at a single instant. For an exact copy, take the snapshot
+    /// while no other thread is allocating or freeing bits.
+    pub fn snapshot(&self) -> SbitmapSnapshot {
+        let per_word = SbitmapSnapshot::entries_per_word(self.shift);
+        let mut words = Vec::with_capacity(self.map_nr * per_word);
+        for i in 0..self.map_nr {
+            let word = self.map[i].word.load(Ordering::Acquire);
+            let word = word & Self::make_mask(self.map_depth(i));
+            words.extend((0..per_word).map(|k| (word >> (k * 64)).low_u64()));
+        }
+
+        SbitmapSnapshot {
+            depth: self.depth,
+            shift: self.shift,
+            policy: self.policy,
+            words,
+        }
+    }
+
+    /// Restore a snapshot into a bitmap with words of type `W`
+    fn restore(snapshot: &SbitmapSnapshot) -> Result<Self, SnapshotError> {
+        snapshot.validate()?;
+        if snapshot.shift > W::Bits::BITS.trailing_zeros() {
+            return Err(SnapshotError::InvalidShift(snapshot.shift));
+        }
+
+        let sb =
+            Self::with_word_type_and_policy(snapshot.depth, Some(snapshot.shift), snapshot.policy);
+
+        let per_word = SbitmapSnapshot::entries_per_word(snapshot.shift);
+        for (i, entries) in snapshot.words.chunks(per_word).enumerate() {
+            let mut word = W::Bits::ZERO;
+            for (k, &entry) in entries.iter().enumerate() {
+                word = word | (W::Bits::from_u64(entry) << (k * 64));
+            }
+            sb.map[i].word.store(word, Ordering::Relaxed);
+        }
+
+        Ok(sb)
+    }
+}
+
+impl Sbitmap {
+    /// Create a new sbitmap with the configuration and allocated bits of a snapshot
+    ///
+    /// Bitmaps with other word types are restored with `TryFrom`, e.g.
+    /// `Sbitmap::<AtomicU32>::try_from(&snapshot)`.
+    ///
+    /// # Returns
+    /// * `Ok(sbitmap)` - Bitmap with exactly the snapshotted bits allocated
+    /// * `Err(error)` - Snapshot is inconsistent or does not fit this platform
+    pub fn from_snapshot(snapshot: &SbitmapSnapshot) -> Result<Self, SnapshotError> {
+        Self::restore(snapshot)
+    }
+}
+
+impl<W: BitmapWord> From<&Sbitmap<W>> for SbitmapSnapshot {
+    fn from(sb: &Sbitmap<W>) -> Self {
+        sb.snapshot()
+    }
+}
+
+impl<W: BitmapWord> TryFrom<&SbitmapSnapshot> for Sbitmap<W> {
+    type Error = SnapshotError;
+
+    fn try_from(snapshot: &SbitmapSnapshot) -> Result<Self, SnapshotError> {
+        Self::restore(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocate_pattern(sb: &Sbitmap, bits: &[usize]) {
+        let mut hint = 0;
+        let mut held = Vec::new();
+        while let Some(bit) = sb.get(&mut hint) {
+            held.push(bit);
+        }
+        for bit in held {
+            if !bits.contains(&bit) {
+                sb.put(bit, &mut hint);
+            }
+        }
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let sb = Sbitmap::new(100, Some(4), true);
+        allocate_pattern(&sb, &[0, 5, 15, 16, 63, 99]);
+
+        let snap = sb.snapshot();
+        assert_eq!(snap.depth(), 100);
+        assert_eq!(snap.shift(), 4);
+        assert!(snap.round_robin());
+        assert_eq!(snap.weight(), 6);
+
+        let restored = Sbitmap::from_snapshot(&snap).expect("Should restore");
+        assert_eq!(restored.depth(), 100);
+        assert_eq!(restored.bits_per_word(), 16);
+        assert_eq!(restored.weight(), 6);
+        for bit in 0..100 {
+            assert_eq!(restored.test_bit(bit), sb.test_bit(bit), "bit {bit}");
+        }
+        assert_eq!(restored.snapshot(), snap);
+    }
+
+    #[test]
+    fn test_snapshot_restored_allocation() {
+        let sb = Sbitmap::new(8, None, false);
+        allocate_pattern(&sb, &[0, 1, 2, 3, 4, 5, 6]);
+
+        let restored = Sbitmap::from_snapshot(&sb.snapshot()).unwrap();
+        let mut hint = 0;
+        assert_eq!(restored.get(&mut hint), Some(7));
+        assert!(restored.get(&mut hint).is_none());
+    }
+
+    #[test]
+    fn test_snapshot_bytes_roundtrip() {
+        for depth in [0, 1, 7, 8, 9, 64, 65, 1000] {
+            let sb = Sbitmap::new(depth, None, depth % 2 == 1);
+            let pattern: Vec<usize> = (0..depth).filter(|b| b % 3 == 0).collect();
+            allocate_pattern(&sb, &pattern);
+
+            let snap = sb.snapshot();
+            let bytes = snap.to_bytes();
+            assert_eq!(bytes.len(), HEADER_LEN + depth.div_ceil(8));
+
+            let decoded = SbitmapSnapshot::from_bytes(&bytes).expect("Should decode");
+            assert_eq!(decoded, snap, "depth {depth}");
+        }
+    }
+
+    #[test]
+    fn test_snapshot_bytes_errors() {
+        let sb = Sbitmap::new(20, Some(3), false);
+        let bytes = sb.snapshot().to_bytes();
+
+        assert_eq!(
+            SbitmapSnapshot::from_bytes(b"NOPE-----------"),
+            Err(SnapshotError::BadMagic)
+        );
+        assert_eq!(
+            SbitmapSnapshot::from_bytes(&bytes[..HEADER_LEN - 1]),
+            Err(SnapshotError::Truncated)
+        );
+        assert_eq!(
+            SbitmapSnapshot::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Truncated)
+        );
+
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert_eq!(
+            SbitmapSnapshot::from_bytes(&extra),
+            Err(SnapshotError::TrailingBytes)
+        );
+
+        let mut version = bytes.clone();
+        version[4] = 99;
+        assert_eq!(
+            SbitmapSnapshot::from_bytes(&version),
+            Err(SnapshotError::UnsupportedVersion(99))
+        );
+
+        let mut policy = bytes.clone();
+        policy[5] = 99;
+        assert_eq!(
+            SbitmapSnapshot::from_bytes(&policy),
+            Err(SnapshotError::UnknownPolicy(99))
+        );
+
+        let mut shift = bytes.clone();
+        shift[6] = 200;
+        assert_eq!(
+            SbitmapSnapshot::from_bytes(&shift),
+            Err(SnapshotError::InvalidShift(200))
+        );
+
+        // depth 20 leaves 4 unused bits in the last byte
+        let mut stray = bytes;
+        *stray.last_mut().unwrap() |= 0x80;
+        assert_eq!(
+            SbitmapSnapshot::from_bytes(&stray),
+            Err(SnapshotError::BitOutOfRange(20))
+        );
+    }
+
+    #[test]
+    fn test_snapshot_policy() {
+        for policy in [
+            AllocPolicy::Hint,
+            AllocPolicy::RoundRobin,
+            AllocPolicy::Lowest,
+            AllocPolicy::Random,
+            AllocPolicy::CpuAffine,
+        ] {
+            let sb = Sbitmap::with_policy(16, None, policy);
+            let snap = SbitmapSnapshot::from_bytes(&sb.snapshot().to_bytes()).unwrap();
+            assert_eq!(snap.policy(), policy);
+            assert_eq!(Sbitmap::from_snapshot(&snap).unwrap().policy(), policy);
+        }
+    }
+
+    #[test]
+    fn test_from_snapshot_validation() {
+        let bad_count = SbitmapSnapshot {
+            depth: 64,
+            shift: 4,
+            policy: AllocPolicy::Hint,
+            words: vec![0; 3],
+        };
+        assert_eq!(
+            Sbitmap::from_snapshot(&bad_count).err(),
+            Some(SnapshotError::WordCountMismatch {
+                expected: 4,
+                found: 3
+            })
+        );
+
+        let stray = SbitmapSnapshot {
+            depth: 20,
+            shift: 4,
+            policy: AllocPolicy::Hint,
+            words: vec![0, 1 << 4],
+        };
+        assert_eq!(
+            Sbitmap::from_snapshot(&stray).err(),
+            Some(SnapshotError::BitOutOfRange(20))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_snapshot_serde() {
+        let sb = Sbitmap::new(40, Some(3), false);
+        allocate_pattern(&sb, &[1, 9, 39]);
+
+        let snap = sb.snapshot();
+        let json = serde_json::to_string(&snap).unwrap();
+        let decoded: SbitmapSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, snap);
+
+        let restored = Sbitmap::from_snapshot(&decoded).unwrap();
+        assert_eq!(restored.weight(), 3);
+        assert!(restored.test_bit(39));
+
+        // Inconsistent input is rejected instead of panicking later
+        for bad in [
+            r#"{"depth":10,"shift":70,"policy":"Hint","words":[0]}"#,
+            r#"{"depth":64,"shift":4,"policy":"Hint","words":[0,0,0]}"#,
+            r#"{"depth":20,"shift":4,"policy":"Hint","words":[0,16]}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<SbitmapSnapshot>(bad).is_err(),
+                "{bad}"
+            );
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Atomic word types usable as sbitmap storage
+//
+// `Sbitmap<W>` stores its bits in words of type `W`. The traits here are
+// sealed: they are implemented for the unsigned atomics of the standard
+// library (or loom's under `--cfg loom`) and, with the `atomic128`
+// feature, for `portable_atomic::AtomicU128`.
+
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use std::fmt::Debug;
+use std::ops::{BitAnd, BitOr, Not, Shl, Shr};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Plain unsigned integer holding the value of a bitmap word
+pub trait WordBits:
+    sealed::Sealed
+    + Copy
+    + Eq
+    + Debug
+    + Send
+    + Sync
+    + 'static
+    + Not<Output = Self>
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + Shl<usize, Output = Self>
+    + Shr<usize, Output = Self>
+{
+    /// Number of bits in the word
+    const BITS: usize;
+    /// No bits set
+    const ZERO: Self;
+    /// Only bit 0 set
+    const ONE: Self;
+    /// All bits set
+    const MAX: Self;
+
+    /// Number of trailing zero bits
+    fn trailing_zeros(self) -> usize;
+
+    /// Number of leading zero bits
+    fn leading_zeros(self) -> usize;
+
+    /// Number of set bits
+    fn count_ones(self) -> usize;
+
+    /// The low 64 bits of the word
+    fn low_u64(self) -> u64;
+
+    /// A word with the low bits taken from `value`, truncating if narrower
+    fn from_u64(value: u64) -> Self;
+}
+
+/// Atomic word usable as sbitmap storage
+///
+/// Implemented for `AtomicU32`, `AtomicU64` and `AtomicUsize`, and for
+/// `portable_atomic::AtomicU128` with the `atomic128` feature.
+pub trait BitmapWord: sealed::Sealed + Send + Sync + 'static {
+    /// Integer type of the word's value
+    type Bits: WordBits;
+
+    #[doc(hidden)]
+    fn new(value: Self::Bits) -> Self;
+    #[doc(hidden)]
+    fn load(&self, order: Ordering) -> Self::Bits;
+    #[doc(hidden)]
+    fn store(&self, value: Self::Bits, order: Ordering);
+    #[doc(hidden)]
+    fn fetch_or(&self, value: Self::Bits, order: Ordering) -> Self::Bits;
+    #[doc(hidden)]
+    fn fetch_and(&self, value: Self::Bits, order: Ordering) -> Self::Bits;
+    #[doc(hidden)]
+    fn compare_exchange_weak(
+        &self,
+        current: Self::Bits,
+        new: Self::Bits,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Self::Bits, Self::Bits>;
+}
+
+macro_rules! impl_word_bits {
+    ($($int:ty),*) => {$(
+        impl sealed::Sealed for $int {}
+
+        impl WordBits for $int {
+            const BITS: usize = <$int>::BITS as usize;
+            const ZERO: Self = 0;
+            const ONE: Self = 1;
+            const MAX: Self = <$int>::MAX;
+
+            #[inline]
+            fn trailing_zeros(self) -> usize {
+                <$int>::trailing_zeros(self) as usize
+            }
+
+            #[inline]
+            fn leading_zeros(self) -> usize {
+                <$int>::leading_zeros(self) as usize
+            }
+
+            #[inline]
+            fn count_ones(self) -> usize {
+                <$int>::count_ones(self) as usize
+            }
+
+            #[inline]
+            fn low_u64(self) -> u64 {
+                self as u64
+            }
+
+            #[inline]
+            fn from_u64(value: u64) -> Self {
+                value as Self
+            }
+        }
+    )*};
+}
+
+impl_word_bits!(u32, u64, usize, u128);
+
+macro_rules! impl_bitmap_word {
+    ($($atomic:ty => $int:ty),*) => {$(
+        impl sealed::Sealed for $atomic {}
+
+        impl BitmapWord for $atomic {
+            type Bits = $int;
+
+            #[inline]
+            fn new(value: $int) -> Self {
+                <$atomic>::new(value)
+            }
+
+            #[inline]
+            fn load(&self, order: Ordering) -> $int {
+                <$atomic>::load(self, order)
+            }
+
+            #[inline]
+            fn store(&self, value: $int, order: Ordering) {
+                <$atomic>::store(self, value, order)
+            }
+
+            #[inline]
+            fn fetch_or(&self, value: $int, order: Ordering) -> $int {
+                <$atomic>::fetch_or(self, value, order)
+            }
+
+            #[inline]
+            fn fetch_and(&self, value: $int, order: Ordering) -> $int {
+                <$atomic>::fetch_and(self, value, order)
+            }
+
+            #[inline]
+            fn compare_exchange_weak(
+                &self,
+                current: $int,
+                new: $int,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$int, $int> {
+                <$atomic>::compare_exchange_weak(self, current, new, success, failure)
+            }
+        }
+    )*};
+}
+
+impl_bitmap_word!(AtomicU32 => u32, AtomicU64 => u64, AtomicUsize => usize);
+
+#[cfg(feature = "atomic128")]
+impl_bitmap_word!(portable_atomic::AtomicU128 => u128);
@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Loom model tests for concurrent get/put/batch operations
+//
+// Run with:
+//   RUSTFLAGS="--cfg loom" cargo test --test loom --release
+
+#![cfg(loom)]
+
+use loom::sync::Arc;
+use loom::thread;
+use sbitmap::Sbitmap;
+
+/// Two threads allocating from a shared word never receive the same bit
+#[test]
+fn loom_get_unique() {
+    loom::model(|| {
+        let sb = Arc::new(Sbitmap::new(2, None, false));
+
+        let sb1 = Arc::clone(&sb);
+        let t1 = thread::spawn(move || {
+            let mut hint = 0;
+            sb1.get(&mut hint)
+        });
+
+        let mut hint = 0;
+        let b0 = sb.get(&mut hint).expect("Should allocate a bit");
+        let b1 = t1.join().unwrap().expect("Should allocate a bit");
+
+        assert_ne!(b0, b1, "Both threads received bit {b0}");
+        assert_eq!(sb.weight(), 2);
+    });
+}
+
+/// A bit freed by one thread can be reallocated by another without
+/// either thread observing a duplicate
+#[test]
+fn loom_get_put_reuse() {
+    loom::model(|| {
+        let sb = Arc::new(Sbitmap::new(1, None, false));
+
+        let sb1 = Arc::clone(&sb);
+        let t1 = thread::spawn(move || {
+            let mut hint = 0;
+            if let Some(bit) = sb1.get(&mut hint) {
+                assert!(sb1.test_bit(bit));
+                sb1.put(bit, &mut hint);
+            }
+        });
+
+        let mut hint = 0;
+        if let Some(bit) = sb.get(&mut hint) {
+            assert!(sb.test_bit(bit));
+            sb.put(bit, &mut hint);
+        }
+
+        t1.join().unwrap();
+        assert_eq!(sb.weight(), 0);
+    });
+}
+
+/// Two concurrent batch allocations never overlap
+#[test]
+fn loom_batch_disjoint() {
+    loom::model(|| {
+        let sb = Arc::new(Sbitmap::new(4, Some(2), false));
+
+        let sb1 = Arc::clone(&sb);
+        let t1 = thread::spawn(move || {
+            let mut hint = 0;
+            sb1.get_batch(2, &mut hint)
+        });
+
+        let mut hint = 0;
+        let s0 = sb.get_batch(2, &mut hint).expect("Should allocate a batch");
+        let s1 = t1.join().unwrap().expect("Should allocate a batch");
+
+        assert!(
+            s0 + 2 <= s1 || s1 + 2 <= s0,
+            "Batches {s0} and {s1} overlap"
+        );
+        assert_eq!(sb.weight(), 4);
+    });
+}
+
+/// A batch allocation racing with a single-bit allocation must not leave
+/// bits set that nobody owns
+#[test]
+fn loom_batch_partial_failure() {
+    loom::model(|| {
+        let sb = Arc::new(Sbitmap::new(4, Some(2), false));
+
+        let sb1 = Arc::clone(&sb);
+        let t1 = thread::spawn(move || {
+            let mut hint = 0;
+            if let Some(bit) = sb1.get(&mut hint) {
+                sb1.put(bit, &mut hint);
+            }
+        });
+
+        let mut hint = 0;
+        if let Some(start) = sb.get_batch(2, &mut hint) {
+            sb.put_batch(start, 2, &mut hint);
+        }
+
+        t1.join().unwrap();
+        assert_eq!(sb.weight(), 0, "Stray bits left set");
+    });
+}
+
+/// Once wait_idle() has seen a frozen map idle, no bit allocated by a
+/// racing get() is still held, and the last put() always wakes the waiter
+#[test]
+fn loom_freeze_wait_idle() {
+    use loom::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    loom::model(|| {
+        let sb = Arc::new(Sbitmap::new(2, None, false));
+        let drained = Arc::new(AtomicBool::new(false));
+
+        let sb1 = Arc::clone(&sb);
+        let drained1 = Arc::clone(&drained);
+        let t1 = thread::spawn(move || {
+            let mut hint = 0;
+            if let Some(bit) = sb1.get(&mut hint) {
+                assert!(
+                    !drained1.load(Ordering::SeqCst),
+                    "Bit {bit} held after drain"
+                );
+                sb1.put(bit, &mut hint);
+            }
+        });
+
+        sb.freeze();
+        assert!(sb.wait_idle(Duration::MAX));
+        drained.store(true, Ordering::SeqCst);
+
+        t1.join().unwrap();
+        assert_eq!(sb.weight(), 0);
+    });
+}
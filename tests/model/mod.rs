@@ -0,0 +1,168 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Reference model of Sbitmap shared by the property tests and the fuzzer
+//
+// Operation sequences are applied to both the bitmap and a trivial
+// Vec<bool>, and every result is checked against what the model allows.
+// Allocation order is not deterministic for most policies, so the model
+// checks that an allocated bit was free and that a failed allocation was
+// justified. `Lowest` is deterministic and must return the lowest fit.
+//
+// tests/proptest_model.rs includes this as `mod model;` and
+// fuzz/fuzz_targets/ops.rs through a `#[path]` attribute, so it must only
+// depend on sbitmap itself (plus `arbitrary` when fuzzing).
+
+use sbitmap::{AllocPolicy, Sbitmap};
+
+/// Every allocation policy, to pick from when generating cases
+pub const POLICIES: [AllocPolicy; 5] = [
+    AllocPolicy::Hint,
+    AllocPolicy::RoundRobin,
+    AllocPolicy::Lowest,
+    AllocPolicy::Random,
+    AllocPolicy::CpuAffine,
+];
+
+/// One step of an operation sequence
+#[derive(Debug, Clone)]
+#[cfg_attr(fuzzing, derive(arbitrary::Arbitrary))]
+pub enum Op {
+    Get,
+    /// Free one of the held single-bit or batch allocations
+    Put(usize),
+    GetBatch(usize),
+    PutBatch(usize),
+    TestBit(usize),
+    Weight,
+    /// Replace the hint; callers may pass any value, including out-of-range ones
+    SetHint(usize),
+}
+
+/// Reference model: one bool per bit plus the bitmap geometry
+struct Model {
+    bits: Vec<bool>,
+    bits_per_word: usize,
+    policy: AllocPolicy,
+}
+
+impl Model {
+    /// Word-aligned range of bits that shares a word with `bitnr`
+    fn word_range(&self, bitnr: usize) -> std::ops::Range<usize> {
+        let start = bitnr - bitnr % self.bits_per_word;
+        start..(start + self.bits_per_word).min(self.bits.len())
+    }
+
+    /// Check whether nr_bits free bits starting at `start` fit in one word
+    fn run_free(&self, start: usize, nr_bits: usize) -> bool {
+        let word = self.word_range(start);
+        start + nr_bits <= word.end && self.bits[start..start + nr_bits].iter().all(|b| !b)
+    }
+
+    /// Check whether the bitmap may fail to allocate nr_bits with `hint`
+    ///
+    /// Every policy searches the whole map, except that `RoundRobin` only
+    /// searches the hint's word from the hint's offset.
+    fn may_fail(&self, nr_bits: usize, hint: usize) -> bool {
+        let depth = self.bits.len();
+        let hint = if hint >= depth { 0 } else { hint };
+        let skipped = match self.policy {
+            AllocPolicy::RoundRobin => self.word_range(hint).start..hint,
+            _ => 0..0,
+        };
+
+        !(0..depth).any(|start| !skipped.contains(&start) && self.run_free(start, nr_bits))
+    }
+
+    /// Check an allocation of nr_bits at `start` against the policy's order
+    fn check_order(&self, start: usize, nr_bits: usize) {
+        if self.policy == AllocPolicy::Lowest {
+            let lowest = (0..start).find(|&bit| self.run_free(bit, nr_bits));
+            assert_eq!(lowest, None, "Lowest allocated {nr_bits} at {start}");
+        }
+    }
+}
+
+/// Apply `ops` to a new bitmap and check every result against the model
+///
+/// # Panics
+/// Panics on the first result the model does not allow.
+pub fn run_case(depth: usize, shift: Option<u32>, policy: AllocPolicy, ops: Vec<Op>) {
+    let sb = Sbitmap::with_policy(depth, shift, policy);
+    let mut model = Model {
+        bits: vec![false; depth],
+        bits_per_word: sb.bits_per_word(),
+        policy,
+    };
+    let mut held: Vec<(usize, usize)> = Vec::new();
+    let mut hint = 0usize;
+
+    for op in ops {
+        match op {
+            Op::Get => {
+                let before = hint;
+                match sb.get(&mut hint) {
+                    Some(bit) => {
+                        assert!(bit < depth, "get returned {bit} beyond depth {depth}");
+                        assert!(!model.bits[bit], "get returned allocated bit {bit}");
+                        model.check_order(bit, 1);
+                        model.bits[bit] = true;
+                        held.push((bit, 1));
+                    }
+                    None => assert!(
+                        model.may_fail(1, before),
+                        "get failed with free bits, hint {before}"
+                    ),
+                }
+            }
+            Op::GetBatch(nr_bits) => {
+                let before = hint;
+                match sb.get_batch(nr_bits, &mut hint) {
+                    Some(start) => {
+                        assert!(nr_bits >= 1 && nr_bits <= model.bits_per_word);
+                        assert!(start + nr_bits <= depth);
+                        assert!(
+                            model.run_free(start, nr_bits),
+                            "get_batch({nr_bits}) returned unavailable range at {start}"
+                        );
+                        model.check_order(start, nr_bits);
+                        model.bits[start..start + nr_bits].fill(true);
+                        held.push((start, nr_bits));
+                    }
+                    None => assert!(
+                        nr_bits == 0
+                            || nr_bits > model.bits_per_word
+                            || model.may_fail(nr_bits, before),
+                        "get_batch({nr_bits}) failed with a free run, hint {before}"
+                    ),
+                }
+            }
+            Op::Put(idx) | Op::PutBatch(idx) => {
+                if held.is_empty() {
+                    continue;
+                }
+                let (start, nr_bits) = held.swap_remove(idx % held.len());
+                if matches!(op, Op::Put(_)) && nr_bits == 1 {
+                    sb.put(start, &mut hint);
+                } else {
+                    sb.put_batch(start, nr_bits, &mut hint);
+                }
+                model.bits[start..start + nr_bits].fill(false);
+            }
+            Op::TestBit(bitnr) => {
+                let expected = model.bits.get(bitnr).copied().unwrap_or(false);
+                assert_eq!(sb.test_bit(bitnr), expected, "test_bit({bitnr})");
+            }
+            Op::Weight => {
+                let expected = model.bits.iter().filter(|b| **b).count();
+                assert_eq!(sb.weight(), expected);
+            }
+            Op::SetHint(new_hint) => hint = new_hint,
+        }
+    }
+
+    // Final state must match the model bit for bit
+    for (bitnr, &allocated) in model.bits.iter().enumerate() {
+        assert_eq!(sb.test_bit(bitnr), allocated, "final bit {bitnr}");
+    }
+    assert_eq!(sb.weight(), held.iter().map(|(_, n)| n).sum::<usize>());
+}
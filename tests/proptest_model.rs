@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Property-based tests of Sbitmap against a trivial Vec<bool> reference
+//
+// Random operation sequences are checked against the model in
+// tests/model, which the fuzzer shares.
+
+mod model;
+
+use model::{run_case, Op, POLICIES};
+use proptest::prelude::*;
+use sbitmap::AllocPolicy;
+
+/// Largest valid shift on this platform
+const MAX_SHIFT: u32 = usize::BITS.trailing_zeros();
+
+fn op_strategy(depth: usize) -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => Just(Op::Get),
+        3 => any::<usize>().prop_map(Op::Put),
+        3 => (0..=(1usize << MAX_SHIFT) + 1).prop_map(Op::GetBatch),
+        2 => any::<usize>().prop_map(Op::PutBatch),
+        2 => (0..depth + 2).prop_map(Op::TestBit),
+        1 => Just(Op::Weight),
+        1 => (0..depth + 2).prop_map(Op::SetHint),
+    ]
+}
+
+fn depth_strategy() -> impl Strategy<Value = usize> {
+    prop_oneof![
+        Just(0usize),
+        Just(1),
+        Just(3),
+        Just(4),
+        Just(usize::BITS as usize - 1),
+        Just(usize::BITS as usize + 1),
+        0..300usize,
+    ]
+}
+
+fn case_strategy() -> impl Strategy<Value = (usize, Option<u32>, AllocPolicy, Vec<Op>)> {
+    (
+        depth_strategy(),
+        prop::option::of(0..=MAX_SHIFT),
+        prop::sample::select(POLICIES.to_vec()),
+    )
+        .prop_flat_map(|(depth, shift, policy)| {
+            (
+                Just(depth),
+                Just(shift),
+                Just(policy),
+                prop::collection::vec(op_strategy(depth), 0..200),
+            )
+        })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn prop_matches_model((depth, shift, policy, ops) in case_strategy()) {
+        run_case(depth, shift, policy, ops);
+    }
+}
+
+#[test]
+fn model_edge_depths() {
+    // Fill and drain each edge depth with every shift and policy
+    for depth in [0, 1, 3, 4, 5, 63, 65, 100] {
+        for shift in (0..=MAX_SHIFT).map(Some).chain([None]) {
+            for policy in POLICIES {
+                let mut ops = vec![Op::Get; depth + 1];
+                ops.push(Op::Weight);
+                ops.extend((0..depth).map(Op::Put));
+                ops.push(Op::Weight);
+                ops.extend([Op::GetBatch(2), Op::GetBatch(4), Op::Weight]);
+                run_case(depth, shift, policy, ops);
+            }
+        }
+    }
+}